@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::fs::write;
+
+use crate::utils::*;
+
+/// everything an output file was built from. if none of it changed, the output doesn't need rebuilding
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub input: PathBuf, // relative to the input dir, or the config dir for feeds
+    pub input_hash: u64,
+    pub template: Option<String>,
+    pub template_hash: u64,
+    pub channels: Vec<String>, // rss channels the page is in, or the channel a feed is for
+    pub rep_hash: u64, // md_replace and the markdown options
+    pub listing_hash: u64, // every page's metadata, for pages that loop over other pages. 0 otherwise
+}
+
+/// output file (relative to the output dir) -> what it was built from
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: BTreeMap<PathBuf, Entry>,
+}
+impl Manifest {
+    /// read a manifest. None if there isn't one
+    pub fn load(p: impl AsRef<Path>) -> Result<Option<Manifest>, StcError> {
+        let s = match read_or_none(p)? {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+
+        let mut entries = BTreeMap::new();
+        for (out, body) in parse_shit_markup(s.trim_end())? {
+            let cfg = scf_to_hashmap(parse_singleline_scf(body.trim_end())?);
+            let get = |k: &str| cfg.get(k).ok_or_else(|| StcError::CfgErr(format!("manifest entry {} missing {}", out, k)));
+            let e = Entry {
+                input: PathBuf::from(get("input")?),
+                input_hash: parse_hash(get("input_hash")?)?,
+                template: cfg.get("template").cloned(),
+                template_hash: parse_hash(get("template_hash")?)?,
+                channels: split_list(get("channels")?),
+                rep_hash: parse_hash(get("rep_hash")?)?,
+                listing_hash: cfg.get("listing_hash").map(|v| parse_hash(v)).transpose()?.unwrap_or(0),
+            };
+            entries.insert(PathBuf::from(out), e);
+        }
+
+        Ok(Some(Manifest { entries }))
+    }
+
+    pub fn save(&self, p: impl AsRef<Path>) -> Result<(), StcError> {
+        let mut blocks = Vec::new();
+        for (out, e) in &self.entries {
+            let mut b = format!("{}\n", os_str_to_str_or_err(out.as_os_str())?);
+            b.push_str(&format!("input={}\n", os_str_to_str_or_err(e.input.as_os_str())?));
+            b.push_str(&format!("input_hash={:016x}\n", e.input_hash));
+            if let Some(t) = &e.template {
+                b.push_str(&format!("template={}\n", t));
+            }
+            b.push_str(&format!("template_hash={:016x}\n", e.template_hash));
+            b.push_str(&format!("channels={}\n", e.channels.join(",")));
+            b.push_str(&format!("rep_hash={:016x}\n", e.rep_hash));
+            b.push_str(&format!("listing_hash={:016x}", e.listing_hash));
+            blocks.push(b);
+        }
+        write(p, blocks.join("\n----\n"))?;
+        Ok(())
+    }
+
+    pub fn get(&self, out: &Path) -> Option<&Entry> {
+        self.entries.get(out)
+    }
+
+    pub fn insert(&mut self, out: PathBuf, e: Entry) {
+        self.entries.insert(out, e);
+    }
+}
+
+fn parse_hash(s: &str) -> Result<u64, StcError> {
+    u64::from_str_radix(s, 16).map_err(|_| StcError::CfgErr(format!("bad hash in manifest: {}", s)))
+}
+
+/// split a comma separated list, dropping empties
+pub fn split_list(s: &str) -> Vec<String> {
+    s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("stc_manifest_t_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut m = Manifest::default();
+        m.insert("blog/a.html".into(), Entry {
+            input: "blog/a.md".into(),
+            input_hash: 1,
+            template: Some("main".into()),
+            template_hash: 0xdeadbeef,
+            channels: vec!["feed1".into(), "feed2".into()],
+            rep_hash: u64::MAX,
+            listing_hash: 3,
+        });
+        m.insert("img.png".into(), Entry {
+            input: "img.png".into(),
+            input_hash: 2,
+            ..Default::default()
+        });
+        m.save(dir.join("manifest")).unwrap();
+
+        let loaded = Manifest::load(dir.join("manifest")).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.entries, m.entries);
+    }
+}
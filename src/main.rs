@@ -1,15 +1,51 @@
-use proc::Processor;
+use proc::{Processor, ProcOpts, Action};
 use utils::StcError;
 
 mod proc;
 mod utils;
 mod walkdir;
 mod rss;
+mod serve;
+mod cache;
+mod template;
+mod sitemap;
+mod scaffold;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e.diagnostic());
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), StcError> {
+    let po = ProcOpts::new()?;
+
+    match &po.action {
+        Action::Serve => serve::serve(&po)?,
+        Action::Clean => proc::clean(&po.dirs()?)?,
+        Action::Init => scaffold::init(&po.dirs()?)?,
+        Action::NewPage { path, title, template } => {
+            scaffold::new_page(&po.dirs()?, path, title.as_deref(), template.as_deref())?
+        }
+        Action::NewPost { title, path, channel, template } => {
+            scaffold::new_post(&po.dirs()?, title, path.as_deref(), channel.as_deref(), template.as_deref())?
+        }
+        Action::Build | Action::Check => {
+            let mut p = Processor::new(&po)?;
+            //println!("{:?}", p);
+            let report = match po.action {
+                Action::Check => p.check()?,
+                _ => p.build()?
+            };
+            if !report.errors.is_empty() || !report.warnings.is_empty() {
+                eprintln!("{}", report.summary());
+            }
+            if !report.errors.is_empty() {
+                return Err(StcError::BuildFailed(report.errors.len()))
+            }
+        }
+    }
 
-fn main() -> Result<(), StcError> {
-    let mut p = Processor::new()?;
-    //println!("{:?}", p);
-    p.build()?;
-    
     Ok(())
 }
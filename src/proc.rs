@@ -1,30 +1,140 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use std::env::{current_dir, set_current_dir};
-use std::fs::{read_to_string, write, copy, create_dir_all, remove_dir_all, File};
+use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::env::{current_dir, set_current_dir, temp_dir};
+use std::ffi::OsString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::fs::{read, read_to_string, copy, hard_link, rename, create_dir_all, remove_dir, remove_dir_all, remove_file};
 
-use argparse::{ArgumentParser, StoreOption, Collect, StoreTrue};
+use clap::{Command, Arg, ArgAction, ArgMatches, ErrorKind, ValueSource, crate_version, value_parser};
 use comrak::{ComrakOptions, markdown_to_html};
 
 use crate::walkdir::WalkDir;
 use crate::utils::*;
-use crate::rss::{FatChannel, RssItem, RssError, get_channels};
+use crate::rss::{FatChannel, FeedContent, RssItem, RssError, get_channels, absolute_links, html_body};
+use crate::cache::{Manifest, Entry};
+use crate::sitemap::{SitemapCfg, SitemapUrl};
+use crate::template::{Template, Replacements, RenderMode, Page, Listing, Scope, resolve_all, expand_includes};
 
 #[derive(Default, Debug)]
 pub struct Processor {
     inp_dir: PathBuf,
-    out_dir: PathBuf,
+    out_dir: PathBuf, // where this build is written. swapped in over final_dir once it's done
+    final_dir: PathBuf,
     cfg_dir: PathBuf,
 
     md_ignore: Vec<PathBuf>,
-    md_replace: HashMap<String, String>,
-    md_templates: HashMap<String, String>,
+    md_replace: Replacements,
+    md_templates: HashMap<String, Template>,
+    partials: HashMap<String, String>,
     md_options: ComrakOptions,
 
-    rss_channels: HashMap<String, FatChannel>
+    rss_channels: HashMap<String, FatChannel>,
+    channels_hash: u64,
+    sitemap: Option<SitemapCfg>,
+
+    cache: Manifest, // what the last build made. empty on a full build, and what this one made once it's done
+    previous: HashSet<PathBuf>, // what the last build made, even on a full build. anything else in the output dir isn't ours
+    keep_foreign: bool,
+    rep_hash: u64,
+
+    jobs: usize,
+    pretty_urls: bool,
+    keep_going: bool,
+
+    pages: Vec<Page>, // every markdown page, for loops
+    listing_hash: u64,
+}
+/// what came out of processing one input file
+struct Processed {
+    out: PathBuf, // relative to the output dir
+    entry: Entry,
+    rebuilt: bool,
+    rss: Option<(Vec<String>, RssItem)>,
+    sitemap: Option<SitemapUrl>,
+}
+/// what went wrong in a build that kept going
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub errors: Vec<StcError>,
+    pub warnings: Vec<(PathBuf, String)>, // input file, message
+}
+impl BuildReport {
+    /// print everything that went wrong, and how much of it there was
+    pub fn summary(&self) -> String {
+        let mut ret = String::new();
+        for (f, w) in &self.warnings {
+            ret.push_str(&format!("warning: {}: {}\n", f.to_string_lossy(), w));
+        }
+        for e in &self.errors {
+            ret.push_str(&e.diagnostic());
+            ret.push('\n');
+        }
+        ret.push_str(&format!("{} error{}, {} warning{}",
+            self.errors.len(), if self.errors.len() == 1 { "" } else { "s" },
+            self.warnings.len(), if self.warnings.len() == 1 { "" } else { "s" }));
+        ret
+    }
+}
+
+/// where a build goes before it's swapped in. a hidden sibling of the output dir, so the rename stays on one filesystem
+fn staging_dir(out: &Path) -> PathBuf {
+    out.with_file_name(format!(".{}.stc_tmp", dir_name(out)))
+}
+
+/// where `check` builds. its own dir in the system temp dir, so it can run alongside a build or serve of the same site
+fn check_dir() -> PathBuf {
+    temp_dir().join(format!("staticcc-check-{}", std::process::id()))
+}
+
+/// the build manifest. next to the output dir rather than in it, so it doesn't get published
+fn manifest_path(out: &Path) -> PathBuf {
+    out.with_file_name(format!(".{}.stc_manifest", dir_name(out)))
 }
-#[derive(Default)]
-struct ProcOpts {
+
+fn old_dir(out: &Path) -> PathBuf {
+    out.with_file_name(format!(".{}.stc_old", dir_name(out)))
+}
+
+fn dir_name(p: &Path) -> String {
+    p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| "build".into())
+}
+
+/// a markdown page, read and split up but not rendered
+struct PageSource {
+    md: String, // the whole file
+    body: String, // after the front matter, with partials expanded
+    cfg: HashMap<String, String>, // front matter
+    sections: HashMap<String, Template>,
+}
+/// what staticcc was asked to do
+#[derive(Default, Clone, Debug)]
+pub enum Action {
+    #[default]
+    Build,
+    Serve,
+    Check,
+    Clean,
+    NewPage {
+        path: PathBuf, // relative to the input dir
+        title: Option<String>,
+        template: Option<String>,
+    },
+    NewPost {
+        title: String,
+        path: Option<PathBuf>, // directory, relative to the input dir
+        channel: Option<String>,
+        template: Option<String>,
+    },
+    Init,
+}
+#[derive(Default, Clone)]
+pub struct ProcOpts {
+    pub action: Action,
+    cli: ArgMatches, // the subcommand's, kept so the config can be re-read under it
+    pub port: Option<u16>,
+
     pub dir: Option<String>,
     pub inp_dir: Option<String>,
     pub out_dir: Option<String>,
@@ -34,208 +144,1036 @@ struct ProcOpts {
     pub md_replace: Vec<String>,
     //pub md_templates: Vec<String>,
     pub md_options: ComrakOptions,
+
+    pub full: bool,
+    pub jobs: Option<usize>,
+    pub pretty_urls: bool,
+    pub keep_going: bool,
+    pub keep_foreign: bool,
+}
+/// the resolved input, output and config directories
+pub struct Dirs {
+    pub inp: PathBuf,
+    pub out: PathBuf,
+    pub cfg: PathBuf,
 }
 
 impl Processor {
-    pub fn new() -> Result<Processor, StcError> {
-        let po = ProcOpts::new(); // get command line options
-
-        let dir = match po.dir {
-            Some(v) => {
-                set_current_dir(&v)?;
-                PathBuf::from(v).canonicalize()?
-            }
-
-            None => current_dir()?
-        };
+    pub fn new(po: &ProcOpts) -> Result<Processor, StcError> {
+        let dirs = po.dirs()?;
 
         let mut p = Processor { // pull out easy stuff
-            inp_dir: dir.join(po.inp_dir.unwrap_or_else(|| String::from("site"))), // FIX THIS
-            out_dir: dir.join(po.out_dir.unwrap_or_else(|| String::from("build"))), // at present relative i/b/c dirs wont work
-            cfg_dir: dir.join(po.cfg_dir.unwrap_or_else(|| String::from("cfg"))), // because join will go relative to the main dir
-            // on second thoughts im not sure i have enough spoons for a fix
+            inp_dir: dirs.inp,
+            out_dir: staging_dir(&dirs.out),
+            final_dir: dirs.out,
+            cfg_dir: dirs.cfg,
 
-            md_ignore: po.md_ignore,
-            md_replace: HashMap::new(),
+            md_ignore: po.md_ignore.clone(),
+            md_replace: Replacements::default(),
             md_templates: HashMap::new(),
-            md_options: po.md_options,
+            partials: HashMap::new(),
+            md_options: po.md_options.clone(),
+
+            rss_channels: HashMap::new(),
+            channels_hash: 0,
+            sitemap: None,
+
+            cache: Manifest::default(),
+            rep_hash: 0,
+
+            jobs: match po.jobs {
+                Some(v) if v > 0 => v,
+                _ => thread::available_parallelism().map(|n| n.get()).unwrap_or(1) // one per core by default
+            },
 
-            rss_channels: HashMap::new()
+            pretty_urls: po.pretty_urls,
+            keep_going: po.keep_going,
+            keep_foreign: po.keep_foreign,
+            previous: HashSet::new(),
+
+            pages: Vec::new(),
+            listing_hash: 0,
         }; // init struct set up
 
-        if p.out_dir.exists() { // make sure build dir exists
-            remove_dir_all(&p.out_dir)?;
+        let previous = match Manifest::load(manifest_path(&p.final_dir)) {
+            Ok(v) => v.unwrap_or_default(),
+            Err(e) => {
+                println!("build manifest unreadable ({:?}), doing a full build", e);
+                Manifest::default()
+            }
+        };
+        p.previous = previous.entries.keys().cloned().collect();
+        if !po.full {
+            p.cache = previous; // incremental, start from the old output
         }
-        create_dir_all(&p.out_dir)?;
 
         // now plug everything in
 
         println!("getting configs");
 
-        match read_or_none(p.cfg_dir.join("md_ignore"))? { // ignores from cfg
-            Some(v) => {
-                for ig in v.split('\n') {
-                    p.md_ignore.push(PathBuf::from(ig));
-                }
+        if let Some(v) = read_or_none(p.cfg_dir.join("md_ignore"))? { // ignores from cfg
+            for ig in v.split('\n') {
+                p.md_ignore.push(PathBuf::from(ig));
             }
-            None => {}
         }
 
         let mut parsed = match read_or_none(p.cfg_dir.join("md_replace"))? { // reps from cfg
             Some(v) => {
-                parse_shit_markup(&v)?
+                parse_shit_markup(&v).map_err(|e| e.in_file(p.cfg_dir.join("md_replace"), &v))?
             }
             None => Vec::new()
         };
-        for r in po.md_replace { // reps from command line
-            parsed.push(parse_rep(&r)?)
+        for r in &po.md_replace { // reps from command line
+            parsed.push(parse_rep(r)?)
+        }
+        p.md_replace = Replacements::new(scf_to_hashmap(parsed));
+
+        let mut reps: Vec<_> = p.md_replace.map().iter().collect(); // hashmap order isn't stable, so sort first
+        reps.sort();
+        p.rep_hash = stable_hash(&(reps, format!("{:?}", p.md_options), p.pretty_urls));
+
+        let partials_dir = p.cfg_dir.join("partials");
+        if partials_dir.is_dir() {
+            println!("finding partials");
+            for entry in WalkDir::new(&partials_dir)? {
+                let entry = entry?;
+                let path = partials_dir.join(&entry);
+                if !path.is_dir() {
+                    let name = String::from(os_str_to_str_or_err(entry.as_os_str())?); // eg. blog/nav
+                    let content = read_to_string(path)?;
+                    println!("found partial {}", name);
+                    let content = content.strip_suffix('\n').unwrap_or(&content).to_owned(); // so a partial can sit inline
+                    p.partials.insert(name, content);
+                }
+            }
         }
-        p.md_replace = scf_to_hashmap(parsed);
 
         println!("finding templates");
         let templates_dir = p.cfg_dir.join("templates");
+        let mut raw_templates = HashMap::new();
         for i in templates_dir.read_dir()? {
             let path = i?.path();
             if !path.is_dir() {
                 let name = String::from(os_str_to_str_or_err(path.file_name().unwrap())?); // should never be None
-                let content = read_to_string(path)?;
+                let content = read_to_string(&path)?;
                 println!("found {}", name);
-                p.md_templates.insert(name, content);
+                let t = expand_includes(&name, &content, &p.partials)
+                    .and_then(|expanded| Template::parse(&name, &expanded))
+                    .map_err(|e| e.in_file(&path, &content))?;
+                raw_templates.insert(name, t);
             }
         }
+        p.md_templates = resolve_all(&raw_templates)?; // parents are looked up here, so a missing one fails the build straight away
         match read_or_none(p.cfg_dir.join("channels"))? {
             None => {},
             Some(v) => {
-                let cfg = scf_to_hashmap(parse_shit_markup(&v)?);
-                p.rss_channels = get_channels(&cfg)?;
+                p.channels_hash = stable_hash(&v);
+                p.rss_channels = parse_shit_markup(&v)
+                    .and_then(|parsed| get_channels(&scf_to_hashmap(parsed)))
+                    .map_err(|e| e.in_file(p.cfg_dir.join("channels"), &v))?;
             }
         }
+        if let Some(v) = read_or_none(p.cfg_dir.join("sitemap"))? {
+            p.sitemap = Some(parse_singleline_scf(v.trim_end())
+                .and_then(|parsed| SitemapCfg::new(&scf_to_hashmap(parsed)))
+                .map_err(|e| e.in_file(p.cfg_dir.join("sitemap"), &v))?);
+        }
 
         Ok(p)
     }
 
+    /// markdown AND NOT ignored
+    fn is_page(&self, path: &Path) -> Result<bool, StcError> {
+        Ok(is_markdown(path)? && !self.md_ignore.iter().any(|ign| path == ign))
+    }
+
+    /// just the front matter side of a page, for other pages to loop over. None if it's not a page
+    fn read_page(&self, path: &Path) -> Result<Option<Page>, StcError> {
+        if !self.is_page(path)? {
+            return Ok(None)
+        }
+        let md = read_to_string(self.inp_dir.join(path))?;
+        let (fm, _) = split_front_matter(&md)?;
+        let cfg = parse_front_matter(fm)?;
+        let channels = cfg.get("rss_chan_id").map(|v| v.split(',').map(String::from).collect()).unwrap_or_default();
+        Ok(Some(Page::new(path.into(), channels, RssItem::meta(&cfg, path, self.pretty_urls)?)))
+    }
+
     /// path MUST be relative to input dir or This Will Not Work
-    fn process_file(&mut self, path: impl AsRef<Path>) -> Result<(), StcError> {
-        if is_markdown(&path)? && !self.md_ignore.iter().any(|ign| path.as_ref() == ign) { // markdown AND NOT ignored
-            self.process_markdown(path)?
+    fn process_file(&self, path: impl AsRef<Path>) -> Result<Processed, StcError> {
+        let path = path.as_ref();
+        if self.is_page(path)? {
+            self.process_markdown(path)
         }
         else { // regular file
-            copy(self.inp_dir.join(&path), self.out_dir.join(&path))?;
+            let entry = Entry {
+                input: path.into(),
+                input_hash: stable_hash(&read(self.inp_dir.join(path))?),
+                ..Default::default()
+            };
+            let rebuilt = self.is_stale(path, &entry);
+            if rebuilt {
+                copy_fresh(self.inp_dir.join(path), self.out_dir.join(path))?;
+            }
+            let sitemap = if path.extension().map(|e| e == "html").unwrap_or(false) { // hand-written html pages go in too
+                let mtime = self.inp_dir.join(path).metadata()?.modified().ok();
+                SitemapUrl::new(&HashMap::new(), os_str_to_str_or_err(path.as_os_str())?, mtime)?
+            }
+            else {
+                None
+            };
+
+            Ok(Processed {
+                out: path.into(), entry, rebuilt, rss: None, sitemap
+            })
         }
+    }
 
-        Ok(())
+    /// true if the output needs (re)making, ie. it's missing or was built from something else
+    fn is_stale(&self, out: &Path, entry: &Entry) -> bool {
+        self.cache.get(out) != Some(entry) || !self.out_dir.join(out).exists()
     }
 
-    fn process_markdown(&mut self, path: impl AsRef<Path>) -> Result<(), StcError> {
-        println!("processing {}", path.as_ref().to_string_lossy());
-        let md = read_to_string(self.inp_dir.join(&path))?;
-        
-        let (fm, document) = split_doc(&md)?;
+    fn process_markdown(&self, path: &Path) -> Result<Processed, StcError> {
+        println!("processing {}", path.to_string_lossy());
+        let PageSource { md, body, cfg, sections } = self.read_source(path)?;
 
-        let mut out_path = self.out_dir.join(&path);
-        out_path.set_extension("html");
-        
-        let mut cfg = HashMap::new(); // get cfg from front matter
-        for c in fm.split('\n') {
-            if c.trim() != "" {
-                let (k, v) = parse_rep(c)?;
-                cfg.insert(k, v);
+        let out = output_path(path, self.pretty_urls);
+
+        let mtime = self.inp_dir.join(path).metadata()?.modified().ok();
+        let sitemap = SitemapUrl::new(&cfg, &page_url(path, self.pretty_urls)?, mtime)?;
+
+        let rss = if cfg.contains_key("rss_chan_id") {
+            Some(RssItem::new(&cfg, path, self.pretty_urls)?)
+        }
+        else {
+            None
+        };
+
+        let main = &String::from("main");
+        let temp_name = cfg.get("template").unwrap_or(main);
+        let template = self.md_templates.get(temp_name).ok_or(StcError::TemplateError(temp_name.to_owned()))?;
+
+        let entry = Entry {
+            input: path.into(),
+            input_hash: stable_hash(&(&md, &body)), // expanded body too, so changed partials are picked up
+            template: Some(temp_name.to_owned()),
+            template_hash: template.hash,
+            channels: rss.as_ref().map(|(ids, _)| ids.clone()).unwrap_or_default(),
+            rep_hash: self.rep_hash,
+            listing_hash: if template.uses_listing() || sections.values().any(|t| t.uses_listing()) {
+                self.listing_hash
             }
+            else {
+                0
+            },
+        };
+        if !self.is_stale(&out, &entry) {
+            println!("{} unchanged", out.to_string_lossy());
+            return Ok(Processed {
+                out, entry, rebuilt: false, rss, sitemap
+            })
         }
 
-        if let Some(_) = cfg.get("rss_chan_id") {
-            let (channels, item) = RssItem::new(&cfg, path.as_ref())?;
-            for id in channels {
-                let chan = self.rss_channels.get_mut(&id).ok_or(RssError::ChannelNotFound(id.into()))?;
-                chan.items.push(item.clone())
+        let listing = Listing {
+            pages: &self.pages, current: path
+        };
+        let mode = match cfg.get("render_mode") { // page beats template
+            Some(v) => RenderMode::parse(v)?,
+            None => template.render_mode.unwrap_or_default()
+        };
+
+        let md_options = self.page_md_options(&cfg)?;
+        let mut document = HashMap::new();
+        let html = match mode {
+            RenderMode::Sections => { // sections become html on their own, the template is already html
+                for (name, t) in &sections {
+                    let md = t.render(&Scope::new(vec![&cfg]), &listing, &self.md_replace);
+                    document.insert(name.clone(), markdown_to_html(&md, &md_options));
+                }
+                let scope = Scope::new(vec![&document, &cfg]); // document blocks first, then fm configs
+                template.render(&scope, &listing, &self.md_replace)
+            }
+            RenderMode::Legacy => { // fill the template, then markdown the lot
+                for (name, t) in &sections { // replacements get made once the whole page is put together
+                    document.insert(name.clone(), t.render(&Scope::new(vec![&cfg]), &listing, &Replacements::default()));
+                }
+                let scope = Scope::new(vec![&document, &cfg]);
+                let filled = template.render(&scope, &listing, &self.md_replace);
+
+                //println!("{}", filled);
+
+                markdown_to_html(&filled, &md_options)
             }
+        };
+
+        let out_file = self.out_dir.join(&out);
+        if let Some(parent) = out_file.parent() { // pretty urls need a directory per page
+            create_dir_all(parent)?;
         }
+        write_fresh(out_file, html)?;
 
-        let main = &String::from("main");
-        let temp_name = cfg.get("template").unwrap_or(main);
-        let mut template = self.md_templates.get(temp_name).ok_or(StcError::TemplateError(temp_name.to_owned()))?.to_owned();
-        
-        for (block_name, block) in document { // document blocks
-            let rep_trigger = format!("##{}##", block_name);
-            template = replace_all_unescaped(&template, &rep_trigger, &block);
+        Ok(Processed {
+            out, entry, rebuilt: true, rss, sitemap
+        })
+    }
+
+    /// point an error at the input file it came from
+    fn locate(&self, path: &Path, e: StcError) -> StcError {
+        let full = self.inp_dir.join(path);
+        match read_to_string(&full) {
+            Ok(src) => e.in_file(full, &src),
+            Err(_) => e // can't read it, so nothing to point at
+        }
+    }
+
+    fn read_source(&self, path: &Path) -> Result<PageSource, StcError> {
+        let md = read_to_string(self.inp_dir.join(path))?;
+
+        let (fm, body) = split_front_matter(&md)?;
+        let body = expand_includes(os_str_to_str_or_err(path.as_os_str())?, body, &self.partials)?; // before splitting, so partials can hold sections
+        let mut sections = HashMap::new(); // sections can have conditionals and loops in them too
+        for (name, content) in split_sections(&body)? {
+            let t = Template::parse(&format!("{} section {}", path.to_string_lossy(), name), &content)?;
+            sections.insert(name, t);
+        }
+        let cfg = parse_front_matter(fm)?; // get cfg from front matter
+
+        Ok(PageSource {
+            md, body, cfg, sections
+        })
+    }
+
+    /// the markdown options, with the page's `md_` front matter on top
+    fn page_md_options(&self, cfg: &HashMap<String, String>) -> Result<Cow<'_, ComrakOptions>, StcError> {
+        let mut ret = Cow::Borrowed(&self.md_options);
+        for (k, v) in cfg {
+            if let Some(opt) = k.strip_prefix("md_") {
+                if !set_md_option(ret.to_mut(), opt, v)? {
+                    return Err(StcError::CfgErr(format!("unknown markdown option: {}", k)).near(&format!("{}={}", k, v)))
+                }
+            }
         }
-        for (k, v) in cfg { // fm configs
-            let rep_trigger = format!("##{}##", k);
-            template = replace_all_unescaped(&template, &rep_trigger, &v);
+        Ok(ret)
+    }
+
+    /// the full html of a feed item, with links made absolute. None if the page doesn't have the section
+    fn item_content(&self, item: &RssItem, what: &FeedContent, prepend: &str) -> Result<Option<String>, StcError> {
+        let html = match what {
+            FeedContent::Page => { // already built, just read it back
+                let built = read_to_string(self.out_dir.join(output_path(&item.path, self.pretty_urls)))?;
+                html_body(&built).to_owned()
+            }
+            FeedContent::Section(name) => {
+                let src = self.read_source(&item.path)?;
+                let t = match src.sections.get(name) {
+                    Some(v) => v,
+                    None => return Ok(None)
+                };
+                let listing = Listing {
+                    pages: &self.pages, current: &item.path
+                };
+                let md = t.render(&Scope::new(vec![&src.cfg]), &listing, &self.md_replace);
+                let md_options = self.page_md_options(&src.cfg)?;
+                markdown_to_html(&md, &md_options)
+            }
+        };
+        Ok(Some(absolute_links(&html, &format!("{}{}", prepend, item.page), prepend)))
+    }
+
+    /// run `f` on every file across `self.jobs` threads. results come back in the same order as `files`
+    fn par_map<T: Send>(&self, files: &[PathBuf], f: impl Fn(&Path) -> T + Sync) -> Vec<T> {
+        let next = AtomicUsize::new(0); // index of the next file nobody's picked up yet
+        let mut results: Vec<Option<T>> = files.iter().map(|_| None).collect();
+        let jobs = self.jobs.min(files.len()).max(1);
+
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..jobs).map(|_| s.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= files.len() {
+                        break
+                    }
+                    done.push((i, f(&files[i])));
+                }
+                done
+            })).collect();
+
+            for w in workers {
+                for (i, r) in w.join().unwrap() { // only fails if the worker panicked, so pass the panic on
+                    results[i] = Some(r);
+                }
+            }
+        });
+
+        results.into_iter().map(|r| r.unwrap()).collect() // every index gets picked up exactly once
+    }
+
+    /// in keep-going mode, note the error and carry on. otherwise stop the build
+    fn fail(&self, report: &mut BuildReport, e: StcError) -> Result<(), StcError> {
+        if self.keep_going {
+            report.errors.push(e);
+            Ok(())
         }
-        for (trig, rep) in &self.md_replace { // global reps
-            template = replace_all_unescaped(&template, &format!("REP={}", trig), rep)
+        else {
+            Err(e)
+        }
+    }
+
+    /// build into a staging dir next to the output dir, and only swap it in if nothing went wrong
+    pub fn build(&mut self) -> Result<BuildReport, StcError> {
+        self.stage()?;
+        let res = self.build_staged();
+        match &res {
+            Ok(r) if r.errors.is_empty() => {
+                let manifest = manifest_path(&self.final_dir);
+                if manifest.exists() { // if we die before the new one is saved, the next build starts from scratch
+                    remove_file(&manifest)?;
+                }
+                self.swap()?;
+                self.cache.save(manifest)?;
+            }
+            _ => {
+                println!("build failed, leaving {} as it was", self.final_dir.to_string_lossy());
+                let _ = remove_dir_all(&self.out_dir); // it'll be cleared out next time anyway
+            }
         }
-        template = replace_unused_tags(&template);
+        res
+    }
 
-        //println!("{}", template);
+    /// build everything somewhere temporary and throw it away, for the errors
+    pub fn check(&mut self) -> Result<BuildReport, StcError> {
+        self.out_dir = check_dir();
+        self.stage()?;
+        let res = self.build_staged();
+        let _ = remove_dir_all(&self.out_dir);
+        res
+    }
 
-        let html = markdown_to_html(&template, &self.md_options);
+    /// fresh staging dir, with the last build's outputs linked in so unchanged pages can be skipped.
+    /// every write into the staging dir goes through `write_fresh`/`copy_fresh`, so the live files never change
+    fn stage(&self) -> Result<(), StcError> {
+        if self.out_dir.exists() { // left over from a build that died
+            remove_dir_all(&self.out_dir)?;
+        }
+        create_dir_all(&self.out_dir)?;
+        for out in self.cache.entries.keys() {
+            let from = self.final_dir.join(out);
+            if from.is_file() {
+                let to = self.out_dir.join(out);
+                if let Some(parent) = to.parent() {
+                    create_dir_all(parent)?;
+                }
+                if hard_link(&from, &to).is_err() { // different filesystem, or one without links
+                    copy(from, to)?;
+                }
+            }
+        }
+        Ok(())
+    }
 
-        write(out_path, html)?;
+    /// put the staging dir where the output dir was
+    fn swap(&self) -> Result<(), StcError> {
+        if !self.final_dir.exists() {
+            rename(&self.out_dir, &self.final_dir)?;
+            return Ok(())
+        }
+        if self.keep_foreign {
+            self.move_foreign(Path::new(""))?;
+        }
+        let old = old_dir(&self.final_dir);
+        if old.exists() {
+            remove_dir_all(&old)?;
+        }
+        rename(&self.final_dir, &old)?;
+        rename(&self.out_dir, &self.final_dir)?;
+        remove_dir_all(old)?;
+        Ok(())
+    }
 
+    /// move everything in the live output dir that staticcc didn't make into the staging dir
+    fn move_foreign(&self, rel: &Path) -> Result<(), StcError> {
+        for entry in self.final_dir.join(rel).read_dir()? {
+            let rel = rel.join(entry?.file_name());
+            if self.previous.contains(&rel) {
+                continue
+            }
+            let (from, to) = (self.final_dir.join(&rel), self.out_dir.join(&rel));
+            if from.is_dir() && self.previous.iter().any(|p| p.starts_with(&rel)) { // mixed, sort it out file by file
+                create_dir_all(&to)?;
+                self.move_foreign(&rel)?;
+            }
+            else if !to.exists() { // ours wins if there's a clash
+                println!("keeping {}", rel.to_string_lossy());
+                rename(from, to)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn build(&mut self) -> Result<(), StcError> {
+    fn build_staged(&mut self) -> Result<BuildReport, StcError> {
         println!("building site");
+        let mut report = BuildReport::default();
 
+        let mut manifest = Manifest::default();
+        let mut dirty_chans = HashSet::new(); // channels with an item that changed, appeared or went away
+
+        let mut files = Vec::new();
         let wd = WalkDir::new(&self.inp_dir)?;
         for entry in wd {
             let entry = entry?;
             println!("found {}", entry.to_string_lossy());
             if self.inp_dir.join(&entry).is_dir() {
-                create_dir_all(self.out_dir.join(&entry))?;
+                create_dir_all(self.out_dir.join(&entry))?; // dirs first, so workers can write into them
             }
             else {
-                self.process_file(entry)?;
+                files.push(entry);
             }
         }
 
-        for (id, c) in &mut self.rss_channels {
-            println!("finalising rss channel {}", id);
-            let mut items = Vec::new();
-            for i in &c.items {
-                items.push(i.finalise(&c.prepend, id));
+        println!("reading front matter");
+        let mut pages = Vec::new();
+        let mut failed = HashSet::new(); // inputs that errored, in keep-going mode
+        for (f, p) in files.iter().zip(self.par_map(&files, |f| self.read_page(f).map_err(|e| self.locate(f, e)))) {
+            match p {
+                Ok(Some(p)) => pages.push(p),
+                Ok(None) => {},
+                Err(e) => {
+                    self.fail(&mut report, e)?;
+                    failed.insert(f.clone());
+                }
             }
-            c.c.items.append(&mut items);
-            let f = File::create(self.out_dir.join(&c.out_file))?;
-            let _ = c.c.write_to(f);
         }
+        files.retain(|f| !failed.contains(f)); // no point trying them twice
+        self.listing_hash = stable_hash(&pages.iter().map(|p| {
+            (&p.path, &p.meta.page, &p.channels, &p.meta.title, &p.meta.description, p.meta.pubdate.map(|d| d.timestamp()))
+        }).collect::<Vec<_>>());
+        self.pages = pages;
 
-        Ok(())
+        println!("processing {} files on {} threads", files.len(), self.jobs.min(files.len()).max(1));
+        let mut sitemap_urls = Vec::new();
+        let mut rebuilt = HashSet::new(); // outputs made this time, for spotting changed enclosures
+        for (f, done) in files.iter().zip(self.par_map(&files, |f| self.process_file(f).map_err(|e| self.locate(f, e)))) { // merge in walk order, so feeds come out the same every time
+            let done = match done {
+                Ok(v) => v,
+                Err(e) => {
+                    self.fail(&mut report, e)?;
+                    failed.insert(f.clone());
+                    continue
+                }
+            };
+            if done.rebuilt {
+                rebuilt.insert(done.out.clone());
+                dirty_chans.extend(done.entry.channels.iter().cloned());
+                if let Some(old) = self.cache.get(&done.out) { // channels it used to be in
+                    dirty_chans.extend(old.channels.iter().cloned());
+                }
+            }
+            if let Some((channels, item)) = done.rss {
+                for id in channels {
+                    match self.rss_channels.get_mut(&id) {
+                        Some(chan) => chan.items.push(item.clone()),
+                        None => self.fail(&mut report, self.locate(&item.path, RssError::ChannelNotFound(id).into()))?
+                    }
+                }
+            }
+            if let Some(u) = done.sitemap {
+                sitemap_urls.push(u);
+            }
+            manifest.insert(done.out, done.entry);
+        }
+
+        if let Some(s) = &self.sitemap { // cheap enough to just redo every time
+            println!("writing sitemap");
+            s.write(&sitemap_urls, &self.out_dir)?;
+            manifest.insert(s.out_file.clone(), Entry {
+                input: PathBuf::from("sitemap"),
+                ..Default::default()
+            });
+        }
+
+        let feeds: HashSet<&PathBuf> = self.rss_channels.values().flat_map(|c| c.outputs.iter().map(|(_, p)| p)).collect();
+        let mut stale = Vec::new();
+        for (out, old) in &self.cache.entries { // made last time but not this time
+            if !manifest.entries.contains_key(out) && !feeds.contains(out) {
+                dirty_chans.extend(old.channels.iter().cloned());
+                stale.push(out.clone());
+            }
+        }
+        for out in stale {
+            println!("removing stale {}", out.to_string_lossy());
+            let p = self.out_dir.join(&out);
+            if p.is_file() {
+                remove_file(p)?;
+            }
+            if let Some(parent) = out.parent().filter(|d| !d.as_os_str().is_empty()) {
+                let out_parent = self.out_dir.join(parent);
+                if out_parent.is_dir() && !self.inp_dir.join(parent).is_dir() && out_parent.read_dir()?.next().is_none() {
+                    remove_dir(out_parent)?; // eg. foo/ from a pretty url page that's gone
+                }
+            }
+        }
+
+        let mut channels = std::mem::take(&mut self.rss_channels); // so items can be filled in while looking at self
+        for (id, c) in &mut channels {
+            report.warnings.extend(c.sort_items(id));
+            for (item_path, e) in c.items.iter_mut().filter_map(|i| i.enclosure.as_mut().map(|e| (i.path.clone(), e))) {
+                let p = PathBuf::from(&e.path);
+                match self.out_dir.join(&p).metadata() {
+                    Ok(m) => e.length = m.len(),
+                    Err(_) => self.fail(&mut report, self.locate(&item_path, RssError::MissingEnclosure(e.path.clone()).into()))?
+                }
+                if rebuilt.contains(&p) { // a new episode file under the same name still changes the feed
+                    dirty_chans.insert(id.clone());
+                }
+            }
+            let mut filled = false;
+            for (format, out_file) in &c.outputs {
+                let entry = Entry {
+                    input: PathBuf::from("channels"),
+                    input_hash: self.channels_hash,
+                    channels: vec![id.clone()],
+                    ..Default::default()
+                };
+                let unchanged = self.cache.get(out_file) == Some(&entry) && self.out_dir.join(out_file).exists();
+                manifest.insert(out_file.clone(), entry);
+                if unchanged && !dirty_chans.contains(id) {
+                    println!("{} feed for channel {} unchanged", format.name(), id);
+                    continue
+                }
+
+                if let Some(what) = c.content.as_ref().filter(|_| !filled) { // once per channel, not per format
+                    for item in &mut c.items {
+                        match self.item_content(item, what, &c.prepend) {
+                            Ok(v) => item.content = v,
+                            Err(e) => self.fail(&mut report, self.locate(&item.path, e))?
+                        }
+                    }
+                    filled = true;
+                }
+                println!("writing {} feed for channel {}", format.name(), id);
+                let out_path = self.out_dir.join(out_file);
+                if let Some(parent) = out_path.parent() {
+                    create_dir_all(parent)?;
+                }
+                let res = c.render(*format, id)
+                    .and_then(|v| write_fresh(out_path, v).map_err(|e| RssError::WriteFailed(id.clone(), e.to_string()).into()));
+                if let Err(e) = res {
+                    self.fail(&mut report, e)?;
+                }
+            }
+        }
+        self.rss_channels = channels;
+        self.cache = manifest; // saved by `build` once it's swapped in
+
+        Ok(report)
     }
 }
 
 impl ProcOpts {
-    pub fn new() -> ProcOpts {
+    pub fn new() -> Result<ProcOpts, StcError> {
         let mut po = ProcOpts::default();
 
+        let m = parse_args(std::env::args_os()).unwrap_or_else(|e| e.exit());
+
+        let sub = match m.subcommand() {
+            Some(("build", s)) => s,
+            Some(("serve", s)) => {
+                po.action = Action::Serve;
+                s
+            }
+            Some(("check", s)) => {
+                po.action = Action::Check;
+                s
+            }
+            Some(("clean", s)) => {
+                po.action = Action::Clean;
+                s
+            }
+            Some(("new", s)) => {
+                let (kind, new) = s.subcommand().unwrap(); // subcommand_required
+                let arg = |id: &str| new.get_one::<String>(id).cloned();
+                po.action = match kind {
+                    "post" => Action::NewPost {
+                        title: arg("title").unwrap(),
+                        path: arg("path").map(PathBuf::from),
+                        channel: arg("channel"),
+                        template: arg("template"),
+                    },
+                    _ => Action::NewPage {
+                        path: PathBuf::from(arg("path").unwrap()),
+                        title: arg("title"),
+                        template: arg("template"),
+                    }
+                };
+                new
+            }
+            Some(("init", s)) => {
+                po.action = Action::Init;
+                s
+            }
+            _ => &m
+        };
+
+        po.dir = sub.get_one::<String>("dir").cloned();
+        po.cfg_dir = sub.get_one::<String>("config").cloned();
+
+        if let Some(v) = &po.dir { // move to the working dir once, so later joins and rebuilds agree
+            set_current_dir(v)?;
+            po.dir = Some(os_str_to_str_or_err(current_dir()?.as_os_str())?.into());
+        }
+        po.cli = sub.clone();
+
+        po.reload()
+    }
+
+    /// the options as they stand now: `cfg/staticcc`, with the command line on top. serve calls this before every
+    /// rebuild, so edits to the config file are picked up
+    pub fn reload(&self) -> Result<ProcOpts, StcError> {
+        let mut po = ProcOpts {
+            action: self.action.clone(),
+            dir: self.dir.clone(),
+            cfg_dir: self.cfg_dir.clone(),
+            cli: self.cli.clone(),
+            ..Default::default()
+        };
         po.md_options.render.unsafe_ = true;
-        
-        {
-            let mut ap = ArgumentParser::new();
+        if let Action::Check = po.action {
+            po.full = true; // unchanged pages have errors too
+            po.keep_going = true;
+        }
 
-            ap.refer(&mut po.dir).add_option(&["-d"], StoreOption, "the working directory");
-            ap.refer(&mut po.inp_dir).add_option(&["-i"], StoreOption, "the input directory, relative to wd");
-            ap.refer(&mut po.out_dir).add_option(&["-o"], StoreOption, "the output directory, relative to wd");
-            ap.refer(&mut po.cfg_dir).add_option(&["-c"], StoreOption, "the config directory, relative to wd");
+        let cfg_file = po.dirs()?.cfg.join("staticcc"); // defaults first, so the command line can override them
+        if let Some(src) = read_or_none(&cfg_file)? {
+            po.apply_config(&src).map_err(|e| e.in_file(&cfg_file, &src))?;
+        }
 
-            ap.refer(&mut po.md_ignore).add_option(&["-I"], Collect, "a file to ignore, relative to the input dir");
-            ap.refer(&mut po.md_replace).add_option(&["-R"], Collect, "a replacement to make in markdown");
-            //ap.refer(&mut po.md_templates).add_option(&["-T"], Collect, "a path to a template file");
+        let sub = &self.cli;
+        let string = |id: &str| sub.get_one::<String>(id).cloned();
+        po.inp_dir = string("input").or(po.inp_dir);
+        po.out_dir = string("output").or(po.out_dir);
 
-            ap.refer(&mut po.md_options.extension.strikethrough).add_option(&["-s"], StoreTrue, "strikethrough");
-            ap.refer(&mut po.md_options.extension.table).add_option(&["-t"], StoreTrue, "tables");
-            ap.refer(&mut po.md_options.extension.autolink).add_option(&["-a"], StoreTrue, "autolink");
-            ap.refer(&mut po.md_options.extension.tasklist).add_option(&["-l"], StoreTrue, "tasklist");
-            ap.refer(&mut po.md_options.extension.superscript).add_option(&["-S"], StoreTrue, "superscript");
-            ap.refer(&mut po.md_options.extension.footnotes).add_option(&["-f"], StoreTrue, "footnotes");
-            ap.refer(&mut po.md_options.extension.description_lists).add_option(&["-D"], StoreTrue, "description lists");
+        if matches!(po.action, Action::Build | Action::Serve | Action::Check) {
+            let flag = |id: &str| sub.get_flag(id);
+            let toggle = |on: &mut bool, id: &str| if flag(id) { // `--x` turns it on, `--no-x` off, neither leaves the config's
+                *on = true;
+            } else if flag(&format!("no-{}", id)) {
+                *on = false;
+            };
+            toggle(&mut po.full, "full");
+            po.jobs = sub.get_one::<usize>("jobs").copied().or(po.jobs);
+            toggle(&mut po.pretty_urls, "pretty-urls");
+            toggle(&mut po.keep_going, "keep-going");
+            toggle(&mut po.keep_foreign, "keep-foreign");
 
-            ap.parse_args_or_exit()
+            po.md_ignore.extend(sub.get_many::<String>("ignore").unwrap_or_default().map(PathBuf::from));
+            po.md_replace.extend(sub.get_many::<String>("replace").unwrap_or_default().cloned()); // after the config's, so these win
+
+            let o = &mut po.md_options;
+            for (id, on) in [
+                ("strikethrough", &mut o.extension.strikethrough),
+                ("tables", &mut o.extension.table),
+                ("autolink", &mut o.extension.autolink),
+                ("tasklist", &mut o.extension.tasklist),
+                ("superscript", &mut o.extension.superscript),
+                ("footnotes", &mut o.extension.footnotes),
+                ("description-lists", &mut o.extension.description_lists),
+                ("tagfilter", &mut o.extension.tagfilter),
+                ("smart", &mut o.parse.smart),
+                ("hardbreaks", &mut o.render.hardbreaks),
+                ("github-pre-lang", &mut o.render.github_pre_lang),
+                ("escape", &mut o.render.escape),
+            ] {
+                toggle(on, id);
+            }
+            if flag("header-ids") {
+                o.extension.header_ids.get_or_insert_with(String::new);
+            } else if flag("no-header-ids") {
+                o.extension.header_ids = None;
+            }
+            if let Some(v) = string("header-id-prefix") {
+                o.extension.header_ids = Some(v);
+            }
+            if let Some(v) = string("front-matter-delimiter") {
+                o.extension.front_matter_delimiter = Some(v);
+            }
+            if let Some(v) = string("default-info-string") {
+                o.parse.default_info_string = Some(v);
+            }
+            if let Some(v) = sub.get_one::<usize>("width") {
+                o.render.width = *v;
+            }
+            let mut safe = !o.render.unsafe_;
+            toggle(&mut safe, "safe");
+            o.render.unsafe_ = !safe;
+        }
+        if let Action::Serve = po.action {
+            po.port = sub.get_one::<u16>("port").copied().or(po.port);
+        }
+
+        Ok(po)
+    }
+
+    /// defaults from `cfg/staticcc`, applied before the command line
+    fn apply_config(&mut self, src: &str) -> Result<(), StcError> {
+        for line in src.split('\n').filter(|l| !l.trim().is_empty()) {
+            let (k, v) = parse_rep(line)?;
+            let bool_val = || parse_bool(&k, &v);
+            match k.as_str() {
+                "input" => self.inp_dir = Some(v),
+                "output" => self.out_dir = Some(v),
+                "ignore" => self.md_ignore.push(PathBuf::from(v)),
+                "replace" => self.md_replace.push(v),
+                "port" => self.port = Some(v.parse().map_err(|_| StcError::BadOption(k, v))?),
+                "jobs" => self.jobs = Some(v.parse().map_err(|_| StcError::BadOption(k, v))?),
+                "full" => self.full = bool_val()?,
+                "pretty_urls" => self.pretty_urls = bool_val()?,
+                "keep_going" => self.keep_going = bool_val()?,
+                "keep_foreign" => self.keep_foreign = bool_val()?,
+                _ => if !set_md_option(&mut self.md_options, &k, &v)? {
+                    return Err(StcError::CfgErr(format!("unknown option: {}", k)).near(line))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// resolve the i/o/c directories against the working directory
+    pub fn dirs(&self) -> Result<Dirs, StcError> {
+        let dir = match &self.dir {
+            Some(v) => PathBuf::from(v),
+            None => current_dir()?
+        };
+
+        Ok(Dirs {
+            inp: dir.join(self.inp_dir.as_deref().unwrap_or("site")), // relative to the working dir, or absolute
+            out: dir.join(self.out_dir.as_deref().unwrap_or("build")),
+            cfg: dir.join(self.cfg_dir.as_deref().unwrap_or("cfg")),
+        })
+    }
+}
+
+/// delete the output dir, and the staging and old dirs a dead build can leave next to it
+pub fn clean(dirs: &Dirs) -> Result<(), StcError> {
+    for d in [staging_dir(&dirs.out), old_dir(&dirs.out), dirs.out.clone()] {
+        if d.exists() {
+            remove_dir_all(&d)?;
+            println!("removed {}", d.to_string_lossy());
+        }
+    }
+    let manifest = manifest_path(&dirs.out);
+    if manifest.exists() {
+        remove_file(&manifest)?;
+        println!("removed {}", manifest.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// the command line, with every subcommand
+fn cli() -> Command<'static> {
+    Command::new("staticcc")
+        .version(crate_version!())
+        .about("a static site generator")
+        .args(site_args())
+        .args(build_args()) // so a bare `staticcc` still builds
+        .subcommand(Command::new("build").about("build the site (the default)").args(build_args()))
+        .subcommand(Command::new("serve").about("build the site, serve it, and rebuild whenever something changes")
+            .args(build_args())
+            .arg(Arg::new("port").short('p').long("port").takes_value(true).value_parser(value_parser!(u16))
+                .help("the port to serve on (default: 8000)")))
+        .subcommand(Command::new("check").about("build the site into a temporary dir and report every error, without touching the output")
+            .args(build_args()))
+        .subcommand(Command::new("clean").about("delete the output directory, and anything a failed build left behind"))
+        .subcommand(Command::new("new").about("make a new page or post").subcommand_required(true)
+            .subcommand(Command::new("page").about("a blank markdown page in the input directory")
+                .arg(Arg::new("path").required(true).help("where to put it, relative to the input dir. .md is added if there's no extension"))
+                .arg(Arg::new("title").long("title").takes_value(true).help("the page title (default: the file name)"))
+                .arg(Arg::new("template").long("template").takes_value(true).help("the template to use (default: main)")))
+            .subcommand(Command::new("post").about("a blog post, with its front matter filled in")
+                .arg(Arg::new("title").required(true).help("the post title. the file name is made from it"))
+                .arg(Arg::new("path").help("the directory to put it in, relative to the input dir (default: the channel's path)"))
+                .arg(Arg::new("channel").long("channel").takes_value(true).help("the channel id from cfg/channels (default: the only one there)"))
+                .arg(Arg::new("template").long("template").takes_value(true).help("the template to use (default: main)"))))
+        .subcommand(Command::new("init").about("set up a new site in the working directory"))
+}
+
+/// parse the command line. build options go after the subcommand, but `-d`/`-i`/`-o`/`-c` can go anywhere
+fn parse_args<I, T>(args: I) -> Result<ArgMatches, clap::Error>
+where I: IntoIterator<Item = T>, T: Into<OsString> + Clone {
+    let m = cli().try_get_matches_from(args)?;
+    if let Some((name, _)) = m.subcommand() { // clap's conflict setting would take the site args too, so check by hand
+        for a in build_args() {
+            if m.value_source(a.get_id()) == Some(ValueSource::CommandLine) {
+                let flag = match a.get_short() {
+                    Some(c) => format!("-{}", c),
+                    None => format!("--{}", a.get_long().unwrap_or_default())
+                };
+                return Err(cli().error(ErrorKind::ArgumentConflict, format!("build options go after the subcommand, eg. `staticcc {} {}`", name, flag)))
+            }
         }
+    }
+    Ok(m)
+}
+
+/// where the site lives. global, so they work before or after a subcommand
+fn site_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("dir").short('d').long("dir").takes_value(true).global(true).help("the working directory"),
+        Arg::new("input").short('i').long("input").takes_value(true).global(true).help("the input directory, relative to wd"),
+        Arg::new("output").short('o').long("output").takes_value(true).global(true).help("the output directory, relative to wd"),
+        Arg::new("config").short('c').long("config").takes_value(true).global(true).help("the config directory, relative to wd"),
+    ]
+}
+
+/// everything that changes how the site is built
+fn build_args() -> Vec<Arg<'static>> {
+    let flag = |id: &'static str, short: char, help: &'static str| Arg::new(id).short(short).long(id).action(ArgAction::SetTrue).help(help);
+    let mut args = vec![
+        flag("full", 'F', "ignore the build manifest and rebuild everything"),
+        Arg::new("jobs").short('j').long("jobs").takes_value(true).value_parser(value_parser!(usize))
+            .help("how many pages to process at once (default: one per core)"),
+        flag("pretty-urls", 'P', "write foo.md to foo/index.html, and link to it as foo/"),
+        flag("keep-going", 'k', "build every page it can, and report all the errors at the end"),
+        flag("keep-foreign", 'K', "keep files in the output dir that staticcc didn't make, like .git or CNAME"),
+
+        Arg::new("ignore").short('I').takes_value(true).action(ArgAction::Append).help("a file to ignore, relative to the input dir"),
+        Arg::new("replace").short('R').takes_value(true).action(ArgAction::Append).help("a replacement to make in markdown"),
+
+        flag("strikethrough", 's', "strikethrough"),
+        flag("tables", 't', "tables"),
+        flag("autolink", 'a', "autolink"),
+        flag("tasklist", 'l', "tasklist"),
+        flag("superscript", 'S', "superscript"),
+        flag("footnotes", 'f', "footnotes"),
+        flag("description-lists", 'D', "description lists"),
+
+        Arg::new("tagfilter").long("tagfilter").action(ArgAction::SetTrue).help("escape the html tags github filters out, like <script>"),
+        Arg::new("smart").long("smart").action(ArgAction::SetTrue).help("smart punctuation: curly quotes, en and em dashes, ellipses"),
+        Arg::new("header-ids").long("header-ids").action(ArgAction::SetTrue).help("give headers an id, so they can be linked to"),
+        Arg::new("header-id-prefix").long("header-id-prefix").takes_value(true).help("give headers an id, starting with this"),
+        Arg::new("hardbreaks").long("hardbreaks").action(ArgAction::SetTrue).help("turn every newline in a paragraph into a <br>"),
+        Arg::new("front-matter-delimiter").long("front-matter-delimiter").takes_value(true)
+            .help("skip a second block of front matter between these, at the start of the markdown"),
+        Arg::new("github-pre-lang").long("github-pre-lang").action(ArgAction::SetTrue).help("put code block languages in <pre lang=\"...\">"),
+        Arg::new("default-info-string").long("default-info-string").takes_value(true).help("the language for code blocks that don't give one"),
+        Arg::new("width").long("width").takes_value(true).value_parser(value_parser!(usize)).help("wrap column for the html (default: 0, don't wrap)"),
+        Arg::new("escape").long("escape").action(ArgAction::SetTrue).help("escape raw html, instead of dropping it when --safe"),
+        Arg::new("safe").long("safe").action(ArgAction::SetTrue).help("don't pass raw html and dangerous links through"),
+    ];
+    // `--no-x` for every flag, so the command line can turn off something the config turned on. last one wins
+    args.extend([
+        ("no-full", "full"), ("no-pretty-urls", "pretty-urls"), ("no-keep-going", "keep-going"), ("no-keep-foreign", "keep-foreign"),
+        ("no-strikethrough", "strikethrough"), ("no-tables", "tables"), ("no-autolink", "autolink"), ("no-tasklist", "tasklist"),
+        ("no-superscript", "superscript"), ("no-footnotes", "footnotes"), ("no-description-lists", "description-lists"),
+        ("no-tagfilter", "tagfilter"), ("no-smart", "smart"), ("no-header-ids", "header-ids"), ("no-hardbreaks", "hardbreaks"),
+        ("no-github-pre-lang", "github-pre-lang"), ("no-escape", "escape"), ("no-safe", "safe"),
+    ].map(|(no, of)| Arg::new(no).long(no).action(ArgAction::SetTrue).overrides_with(of).hide(true)));
+    args
+}
+
+fn parse_bool(k: &str, v: &str) -> Result<bool, StcError> {
+    match v {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(StcError::BadOption(k.into(), v.into()))
+    }
+}
+
+/// set one of comrak's options by name, from `cfg/staticcc` or a page's `md_` front matter. false if there's no such option
+pub fn set_md_option(o: &mut ComrakOptions, k: &str, v: &str) -> Result<bool, StcError> {
+    let opt_str = || Some(v.to_owned()).filter(|v| !v.is_empty()); // empty turns it off
+    match k {
+        "strikethrough" => o.extension.strikethrough = parse_bool(k, v)?,
+        "tables" => o.extension.table = parse_bool(k, v)?,
+        "autolink" => o.extension.autolink = parse_bool(k, v)?,
+        "tasklist" => o.extension.tasklist = parse_bool(k, v)?,
+        "superscript" => o.extension.superscript = parse_bool(k, v)?,
+        "footnotes" => o.extension.footnotes = parse_bool(k, v)?,
+        "description_lists" => o.extension.description_lists = parse_bool(k, v)?,
+        "tagfilter" => o.extension.tagfilter = parse_bool(k, v)?,
+        "header_ids" => o.extension.header_ids = match parse_bool(k, v)? {
+            true => o.extension.header_ids.take().or_else(|| Some(String::new())), // keep the prefix if there is one
+            false => None
+        },
+        "header_id_prefix" => o.extension.header_ids = opt_str(),
+        "front_matter_delimiter" => o.extension.front_matter_delimiter = opt_str(),
+        "smart" => o.parse.smart = parse_bool(k, v)?,
+        "default_info_string" => o.parse.default_info_string = opt_str(),
+        "hardbreaks" => o.render.hardbreaks = parse_bool(k, v)?,
+        "github_pre_lang" => o.render.github_pre_lang = parse_bool(k, v)?,
+        "width" => o.render.width = v.parse().map_err(|_| StcError::BadOption(k.into(), v.into()))?,
+        "unsafe_html" => o.render.unsafe_ = parse_bool(k, v)?,
+        "escape" => o.render.escape = parse_bool(k, v)?,
+        _ => return Ok(false)
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_args_t() {
+        let m = parse_args(["staticcc", "-d", "x", "build"]).unwrap();
+        let (name, sub) = m.subcommand().unwrap();
+        assert_eq!(name, "build");
+        assert_eq!(sub.get_one::<String>("dir").map(String::as_str), Some("x"));
+
+        assert!(parse_args(["staticcc", "-d", "x", "serve", "-p", "1"]).is_ok());
+        assert!(parse_args(["staticcc", "new", "-d", "x", "page", "about"]).is_ok());
+        assert!(parse_args(["staticcc", "-F", "-d", "x"]).is_ok());
+        assert!(parse_args(["staticcc", "-F", "build"]).is_err());
+    }
+
+    #[test]
+    fn reload_t() {
+        let root = std::env::temp_dir().join(format!("stc_reload_t_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("cfg")).unwrap();
+        std::fs::write(root.join("cfg/staticcc"), "tables=true\nfootnotes=true\nunsafe_html=false\nkeep_going=true").unwrap();
+        let m = parse_args(["staticcc", "build", "--no-tables", "--footnotes", "--no-footnotes", "--no-safe", "-k"]).unwrap();
+        let po = ProcOpts {
+            dir: Some(root.to_string_lossy().into()),
+            cli: m.subcommand().unwrap().1.clone(),
+            ..Default::default()
+        }.reload();
+        std::fs::remove_dir_all(&root).unwrap();
+        let po = po.unwrap();
+        assert!(!po.md_options.extension.table);
+        assert!(!po.md_options.extension.footnotes); // last one wins
+        assert!(po.md_options.render.unsafe_);
+        assert!(po.keep_going);
+
+        assert!(parse_args(["staticcc", "build", "--no-tables", "--tables"]).unwrap().subcommand().unwrap().1.get_flag("tables"));
+    }
+
+    #[test]
+    fn apply_config_t() {
+        let mut po = ProcOpts::default();
+        po.apply_config("tables=true\n\noutput=out\nreplace=a=b\njobs=2\nheader_id_prefix=h-\nunsafe_html=false").unwrap();
+        assert!(po.md_options.extension.table);
+        assert!(!po.md_options.render.unsafe_);
+        assert_eq!(po.md_options.extension.header_ids.as_deref(), Some("h-"));
+        assert_eq!(po.out_dir.as_deref(), Some("out"));
+        assert_eq!(po.md_replace, vec!["a=b".to_owned()]);
+        assert_eq!(po.jobs, Some(2));
+
+        assert!(matches!(po.apply_config("width=wide"), Err(StcError::BadOption(..))));
+        assert!(po.apply_config("tabels=true").is_err());
+    }
 
-        po
+    #[test]
+    fn set_md_option_t() {
+        let mut o = ComrakOptions::default();
+        assert!(set_md_option(&mut o, "header_id_prefix", "x-").unwrap());
+        set_md_option(&mut o, "header_ids", "true").unwrap();
+        assert_eq!(o.extension.header_ids.as_deref(), Some("x-")); // prefix survives
+        set_md_option(&mut o, "header_ids", "false").unwrap();
+        assert_eq!(o.extension.header_ids, None);
+        set_md_option(&mut o, "header_id_prefix", "x-").unwrap();
+        set_md_option(&mut o, "header_id_prefix", "").unwrap();
+        assert_eq!(o.extension.header_ids, None);
+        assert!(!set_md_option(&mut o, "nope", "true").unwrap());
     }
 }
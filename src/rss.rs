@@ -2,50 +2,81 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use chrono::{DateTime, Utc};
 use crate::utils::*;
+use crate::cache::split_list;
 use thiserror::Error;
-use rss::{Channel, Item, ChannelBuilder, ItemBuilder, ImageBuilder, Guid};
+use rss::{Channel, Item, ChannelBuilder, ItemBuilder, ImageBuilder, Guid, Category, Enclosure};
+use atom_syndication::{Feed, FeedBuilder, EntryBuilder, LinkBuilder, PersonBuilder, ContentBuilder, CategoryBuilder, Text};
+use serde_json::{json, Value};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct RssItem {
-    page: String, // path relative to site root
+    pub page: String, // path relative to site root
+    pub path: PathBuf, // the markdown it came from, relative to the input dir
     // guid is "{url}@{channel_id}"
-    title: String,
-    description: String,
-    pubdate: Option<DateTime<Utc>>,
+    pub title: String,
+    pub description: String,
+    pub pubdate: Option<DateTime<Utc>>,
+    pub content: Option<String>, // full html, for channels that ask for it. filled in just before the feed is written
+    pub categories: Vec<String>,
+    pub author: Option<String>,
+    pub comments: Option<String>, // url
+    pub enclosure: Option<ItemEnclosure>,
+}
+
+/// a file attached to an item, eg. a podcast episode
+#[derive(Debug, Clone, Default)]
+pub struct ItemEnclosure {
+    pub path: String, // from site root
+    pub length: u64, // bytes, from the file in the output dir. filled in just before the feed is written
+}
+impl ItemEnclosure {
+    pub fn mime_type(&self) -> &'static str {
+        content_type(Path::new(&self.path))
+    }
 }
 
 impl RssItem {
-    pub fn new(front_matter: &HashMap<String, String>, path: &Path) -> Result<(Vec<String>, RssItem), StcError> {
-        let mut path = PathBuf::from(path);
-        path.set_extension("html");
-        let page = os_str_to_str_or_err(path.as_os_str())?.into();
-        println!("creating rss item at {}", page);
+    pub fn new(front_matter: &HashMap<String, String>, path: &Path, pretty: bool) -> Result<(Vec<String>, RssItem), StcError> {
         let channel_ids = front_matter.get("rss_chan_id").unwrap().to_owned(); // will always exist, as this method will only be called when that key exists
         let mut cids_owned = Vec::new();
         for cid in channel_ids.split(',') {
             cids_owned.push(String::from(cid))
         }
-        let title = match front_matter.get("rss_title") {
-            Some(v) => v,
-            None => {
-                match front_matter.get("title") { // try page title as well, helps reduce magic numbers
-                    Some(v) => v,
-                    None => return Err(RssError::MissingTitle.into())
-                }
-            }
-        }.to_owned();
+        if !front_matter.contains_key("rss_title") && !front_matter.contains_key("title") {
+            return Err(RssError::MissingTitle.into())
+        }
+
+        let r = RssItem::meta(front_matter, path, pretty)?;
+        println!("creating rss item at {}", r.page);
+        Ok((cids_owned, r))
+    }
+
+    /// the same data, for any page. a missing title is just empty
+    pub fn meta(front_matter: &HashMap<String, String>, path: &Path, pretty: bool) -> Result<RssItem, StcError> {
+        let page = page_url(path, pretty)?;
+        let title = front_matter.get("rss_title")
+            .or_else(|| front_matter.get("title")) // try page title as well, helps reduce magic numbers
+            .cloned().unwrap_or_default();
         let pubdate = match front_matter.get("rss_pubdate") {
             None => None, // optional
             Some(v) => {
-                Some(DateTime::parse_from_rfc2822(v).map_err(|e| RssError::BadPubdate(e))?.with_timezone(&Utc))
+                Some(DateTime::parse_from_rfc2822(v).map_err(RssError::BadPubdate)?.with_timezone(&Utc))
             }
         };
         let description = front_matter.get("rss_description").map(|s| s.to_owned()).unwrap_or("".into()); // required for rss, but not for staticcc
 
-        let r = RssItem {
-            pubdate, page, title, description
-        };
-        Ok((cids_owned, r))
+        Ok(RssItem {
+            pubdate, page, title, description,
+            path: path.into(),
+            content: None,
+            categories: front_matter.get("rss_categories").map(|v| split_list(v)).unwrap_or_default(),
+            author: front_matter.get("rss_author").cloned(),
+            comments: front_matter.get("rss_comments").cloned(),
+            enclosure: front_matter.get("rss_enclosure").map(|v| ItemEnclosure {
+                path: v.trim_start_matches('/').into(),
+                length: 0,
+            }),
+        })
     }
 
     pub fn finalise(&self, prepend: &str, _cid: &str) -> Item {
@@ -59,69 +90,390 @@ impl RssItem {
         ib.guid(guid);
         ib.description(self.description.clone());
         ib.pub_date(self.pubdate.map(|d| d.to_rfc2822())); // chefs kiss
+        ib.content(self.content.clone()); // content:encoded
+        ib.categories(self.categories.iter().map(|c| Category { name: c.clone(), domain: None }).collect::<Vec<_>>());
+        ib.author(self.author.clone());
+        ib.comments(self.comments.clone());
+        ib.enclosure(self.enclosure.as_ref().map(|e| Enclosure {
+            url: format!("{}{}", prepend, e.path),
+            length: e.length.to_string(),
+            mime_type: e.mime_type().into(),
+        }));
 
         ib.build()
     }
+
+    /// the same item as an atom entry. entries without a pubdate are stamped with `fallback`
+    pub fn finalise_atom(&self, prepend: &str, fallback: DateTime<Utc>) -> atom_syndication::Entry {
+        let url = format!("{}{}", prepend, &self.page);
+        let date = self.pubdate.unwrap_or(fallback);
+        let mut eb = EntryBuilder::default();
+        eb.title(Text::plain(self.title.clone()));
+        eb.id(url.clone()); // same as the rss guid
+        eb.link(LinkBuilder::default().href(url).rel("alternate").build());
+        eb.updated(date);
+        eb.published(self.pubdate.map(|d| d.into()));
+        eb.categories(self.categories.iter().map(|c| CategoryBuilder::default().term(c.clone()).build()).collect::<Vec<_>>());
+        if let Some(a) = &self.author {
+            eb.author(PersonBuilder::default().name(a.clone()).build());
+        }
+        if let Some(e) = &self.enclosure {
+            eb.link(LinkBuilder::default()
+                .href(format!("{}{}", prepend, e.path))
+                .rel("enclosure")
+                .mime_type(Some(e.mime_type().into()))
+                .length(Some(e.length.to_string()))
+                .build());
+        }
+        if !self.description.is_empty() {
+            eb.summary(Some(Text::plain(self.description.clone())));
+        }
+        if let Some(v) = &self.content { // without it the summary is enough, entries with an alternate link don't need content
+            eb.content(ContentBuilder::default()
+                .value(Some(v.clone()))
+                .content_type(Some("html".into()))
+                .build());
+        }
+
+        eb.build()
+    }
+
+    /// the same item as a json feed item
+    pub fn finalise_json(&self, prepend: &str) -> Value {
+        let url = format!("{}{}", prepend, &self.page);
+        let mut item = json!({
+            "id": url,
+            "url": url,
+            "title": self.title,
+            "summary": self.description,
+        });
+        match &self.content { // json feed needs some content, and the description is plain text
+            Some(v) => item["content_html"] = json!(v),
+            None => item["content_text"] = json!(self.description),
+        }
+        if let Some(d) = self.pubdate {
+            item["date_published"] = json!(d.to_rfc3339());
+        }
+        if !self.categories.is_empty() {
+            item["tags"] = json!(self.categories);
+        }
+        if let Some(a) = &self.author {
+            item["authors"] = json!([{ "name": a }]);
+        }
+        if let Some(e) = &self.enclosure {
+            item["attachments"] = json!([{
+                "url": format!("{}{}", prepend, e.path),
+                "mime_type": e.mime_type(),
+                "size_in_bytes": e.length,
+            }]);
+        }
+        item
+    }
 }
 
 pub fn get_channels(cfg: &HashMap<String, String>) -> Result<HashMap<String, FatChannel>, StcError> {
     let mut ret = HashMap::new();
 
     for (id, data) in cfg {
-        let cfg_inner = scf_to_hashmap(parse_singleline_scf(&data)?);
-        let mut b = ChannelBuilder::default();
-        let prepend = cfg_inner.get("prepend").ok_or(RssError::MissingPrepend)?;
-
-        let title = cfg_inner.get("title").ok_or(RssError::MissingTitle)?;
-        b.title(title);
-        b.description(cfg_inner.get("description").unwrap_or(&"".into()));
-        let link = format!("{}{}", prepend, cfg_inner.get("path").ok_or(RssError::MissingLink)?);
-        b.link(&link);
-        b.docs(String::from("https://www.rssboard.org/rss-specification"));
-        match cfg_inner.get("image") {
-            None => {},
-            Some(v) => {
-                let img_path = format!("{}{}", prepend, v);
-                let mut ib = ImageBuilder::default();
-                ib.url(img_path);
-                ib.link(&link);
-                ib.title(title);
-                b.image(ib.build());
+        let fc = get_channel(data).map_err(|e| e.near(id))?;
+        ret.insert(id.into(), fc);
+    }
+
+    Ok(ret)
+}
+
+fn get_channel(data: &str) -> Result<FatChannel, StcError> {
+    let cfg_inner = parse_front_matter(data)?; // blank lines are fine, like the last channel in a file with a trailing newline
+    let mut b = ChannelBuilder::default();
+    let prepend = cfg_inner.get("prepend").ok_or(RssError::MissingPrepend)?;
+
+    let title = cfg_inner.get("title").ok_or(RssError::MissingTitle)?;
+    b.title(title);
+    b.description(cfg_inner.get("description").unwrap_or(&"".into()));
+    let link = format!("{}{}", prepend, cfg_inner.get("path").ok_or(RssError::MissingLink)?);
+    b.link(&link);
+    b.docs(String::from("https://www.rssboard.org/rss-specification"));
+    b.language(cfg_inner.get("language").cloned());
+    b.copyright(cfg_inner.get("copyright").cloned());
+    b.managing_editor(cfg_inner.get("managingEditor").cloned());
+    if let Some(v) = cfg_inner.get("ttl") {
+        v.parse::<u32>().map_err(|_| RssError::BadTtl(v.to_owned()))?; // minutes
+        b.ttl(Some(v.to_owned()));
+    }
+    let categories = cfg_inner.get("categories").map(|v| split_list(v)).unwrap_or_default();
+    b.categories(categories.iter().map(|c| Category { name: c.clone(), domain: None }).collect::<Vec<_>>());
+    match cfg_inner.get("image") {
+        None => {},
+        Some(v) => {
+            let img_path = format!("{}{}", prepend, v);
+            let mut ib = ImageBuilder::default();
+            ib.url(img_path);
+            ib.link(&link);
+            ib.title(title);
+            b.image(ib.build());
+        }
+    }
+
+    let formats = match cfg_inner.get("format") {
+        Some(v) => split_list(v).iter().map(|f| FeedFormat::parse(f)).collect::<Result<Vec<_>, _>>()?,
+        None => vec![FeedFormat::Rss]
+    };
+    let mut outputs: Vec<(FeedFormat, PathBuf)> = Vec::new();
+    for f in formats {
+        if outputs.iter().any(|(g, _)| *g == f) {
+            continue
+        }
+        // `atom_outfile` etc. win, otherwise the first format to want it gets plain `outfile`
+        let path = match cfg_inner.get(&format!("{}_outfile", f.name())) {
+            Some(v) => PathBuf::from(v),
+            None => match cfg_inner.get("outfile") {
+                Some(v) if !outputs.iter().any(|(_, p)| p == Path::new(v)) => PathBuf::from(v),
+                _ => return Err(RssError::MissingPath.into())
             }
+        };
+        if !stays_inside(&path) {
+            return Err(RssError::OutsideOutput(path.to_string_lossy().into()).into())
         }
+        outputs.push((f, path));
+    }
 
-        let path = cfg_inner.get("outfile").ok_or(RssError::MissingPath)?;
-        let fc = FatChannel::new(b.build(), path, prepend);
-        ret.insert(id.into(), fc);
+    let mut fc = FatChannel::new(b.build(), outputs, prepend);
+    fc.author = cfg_inner.get("author").cloned();
+    fc.limit = match cfg_inner.get("limit") {
+        Some(v) => Some(v.parse().map_err(|_| RssError::BadLimit(v.to_owned()))?),
+        None => None
+    };
+    fc.content = cfg_inner.get("content").map(|v| match v.as_str() {
+        "page" => FeedContent::Page,
+        _ => FeedContent::Section(v.to_owned())
+    });
+
+    Ok(fc)
+}
+
+/// what goes in an item's full content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedContent {
+    Page, // the whole built page, or its <body> if it has one
+    Section(String), // one document section, rendered on its own
+}
+
+/// the kinds of feed document a channel can be written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+impl FeedFormat {
+    pub fn parse(s: &str) -> Result<FeedFormat, StcError> {
+        match s {
+            "rss" => Ok(FeedFormat::Rss),
+            "atom" => Ok(FeedFormat::Atom),
+            "json" => Ok(FeedFormat::Json),
+            _ => Err(RssError::BadFormat(s.into()).into())
+        }
     }
 
-    Ok(ret)
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "rss",
+            FeedFormat::Atom => "atom",
+            FeedFormat::Json => "json",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct FatChannel {
     pub c: Channel,
-    pub out_file: PathBuf,
+    pub outputs: Vec<(FeedFormat, PathBuf)>, // relative to the output dir
     pub items: Vec<RssItem>,
-    pub prepend: String
+    pub prepend: String,
+    pub author: Option<String>,
+    pub content: Option<FeedContent>,
+    pub limit: Option<usize>, // most items to keep, newest first
 }
 impl FatChannel {
-    pub fn new(c: Channel, p: impl AsRef<Path>, prepend: &str) -> FatChannel {
+    pub fn new(c: Channel, outputs: Vec<(FeedFormat, PathBuf)>, prepend: &str) -> FatChannel {
         FatChannel {
             c, items: Vec::new(),
-            out_file: PathBuf::from(p.as_ref()),
-            prepend: prepend.into()
+            outputs,
+            prepend: prepend.into(),
+            author: None,
+            content: None,
+            limit: None,
         }
     }
+
+    /// newest items first, cut down to the limit, and the channel dated from the newest one.
+    /// returns a warning for every item without a pubdate
+    pub fn sort_items(&mut self, cid: &str) -> Vec<(PathBuf, String)> {
+        let warnings = self.items.iter().filter(|i| i.pubdate.is_none())
+            .map(|i| (i.path.clone(), format!("no rss_pubdate, putting it at the end of channel {}", cid)))
+            .collect();
+        self.items.sort_by_key(|i| std::cmp::Reverse(i.pubdate)); // stable, and None sorts last when reversed
+        if let Some(n) = self.limit {
+            self.items.truncate(n);
+        }
+        let newest = self.items.first().and_then(|i| i.pubdate).map(|d| d.to_rfc2822());
+        self.c.set_pub_date(newest.clone());
+        self.c.set_last_build_date(newest);
+        warnings
+    }
+
+    /// the whole feed document, ready to be written out
+    pub fn render(&self, format: FeedFormat, cid: &str) -> Result<Vec<u8>, StcError> {
+        let res = match format {
+            FeedFormat::Rss => {
+                let mut c = self.c.clone();
+                c.set_items(self.items.iter().map(|i| i.finalise(&self.prepend, cid)).collect::<Vec<_>>());
+                c.write_to(Vec::new()).map_err(|e| e.to_string())
+            }
+            FeedFormat::Atom => self.atom().write_to(Vec::new()).map_err(|e| e.to_string()),
+            FeedFormat::Json => serde_json::to_vec_pretty(&self.json()).map_err(|e| e.to_string()),
+        };
+        Ok(res.map_err(|e| RssError::WriteFailed(cid.into(), e))?)
+    }
+
+    /// where a format's feed ends up, for the feeds that link to themselves
+    fn self_url(&self, format: FeedFormat) -> Option<String> {
+        self.outputs.iter().find(|(f, _)| *f == format)
+            .map(|(_, p)| format!("{}{}", self.prepend, p.to_string_lossy()))
+    }
+
+    fn atom(&self) -> Feed {
+        let now = Utc::now();
+        let updated = self.items.iter().filter_map(|i| i.pubdate).max().unwrap_or(now);
+        let self_url = self.self_url(FeedFormat::Atom);
+
+        let mut fb = FeedBuilder::default();
+        fb.title(Text::plain(self.c.title()));
+        fb.id(self.c.link());
+        fb.updated(updated);
+        fb.link(LinkBuilder::default().href(self.c.link()).rel("alternate").build());
+        if let Some(v) = self_url {
+            fb.link(LinkBuilder::default().href(v).rel("self").mime_type(Some("application/atom+xml".into())).build());
+        }
+        if !self.c.description().is_empty() {
+            fb.subtitle(Some(Text::plain(self.c.description())));
+        }
+        // atom wants an author somewhere, and items don't have one, so fall back on the feed title
+        let author = self.author.clone().unwrap_or_else(|| self.c.title().into());
+        fb.author(PersonBuilder::default().name(author).build());
+        fb.logo(self.c.image().map(|i| i.url().to_owned()));
+        fb.rights(self.c.copyright().map(Text::plain));
+        fb.categories(self.c.categories().iter().map(|c| CategoryBuilder::default().term(c.name.clone()).build()).collect::<Vec<_>>());
+        fb.entries(self.items.iter().map(|i| i.finalise_atom(&self.prepend, now)).collect::<Vec<_>>());
+
+        let mut feed = fb.build();
+        feed.set_lang(self.c.language().map(String::from));
+        feed
+    }
+
+    fn json(&self) -> Value {
+        let mut feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.c.title(),
+            "home_page_url": self.c.link(),
+            "items": self.items.iter().map(|i| i.finalise_json(&self.prepend)).collect::<Vec<_>>(),
+        });
+        if let Some(v) = self.self_url(FeedFormat::Json) {
+            feed["feed_url"] = json!(v);
+        }
+        if !self.c.description().is_empty() {
+            feed["description"] = json!(self.c.description());
+        }
+        if let Some(i) = self.c.image() {
+            feed["icon"] = json!(i.url());
+        }
+        if let Some(a) = &self.author {
+            feed["authors"] = json!([{ "name": a }]);
+        }
+        if let Some(l) = self.c.language() {
+            feed["language"] = json!(l);
+        }
+        feed
+    }
+}
+
+/// the inside of the <body> tag, or all of it if there isn't one
+pub fn html_body(html: &str) -> &str {
+    let start = match html.find("<body") {
+        Some(i) => match html[i..].find('>') {
+            Some(j) => i + j + 1,
+            None => return html
+        },
+        None => return html
+    };
+    let end = html.rfind("</body>").filter(|e| *e >= start).unwrap_or(html.len());
+    html[start..end].trim()
+}
+
+/// make every relative `href` and `src` in some html absolute, so it still works when a feed reader shows it
+pub fn absolute_links(html: &str, page_url: &str, prepend: &str) -> String {
+    let b = html.as_bytes();
+    let mut ret = String::with_capacity(html.len());
+    let mut i = 0;
+    while i < html.len() {
+        let attr = ["href=", "src="].iter()
+            .find(|a| i > 0 && b[i - 1].is_ascii_whitespace() && html[i..].starts_with(**a));
+        if let Some(a) = attr {
+            let q = i + a.len(); // the opening quote
+            if let Some(quote) = b.get(q).filter(|c| **c == b'"' || **c == b'\'') {
+                if let Some(len) = html[q + 1..].find(*quote as char) {
+                    ret.push_str(&html[i..=q]);
+                    ret.push_str(&absolute_url(&html[q + 1..q + 1 + len], page_url, prepend));
+                    i = q + 1 + len; // closing quote gets pushed below
+                }
+            }
+        }
+        let c = html[i..].chars().next().unwrap(); // i is always on a char boundary
+        ret.push(c);
+        i += c.len_utf8();
+    }
+    ret
+}
+
+fn absolute_url(url: &str, page_url: &str, prepend: &str) -> String {
+    let scheme = url.find(':').map(|i| !url[..i].contains(['/', '?', '#'])).unwrap_or(false); // https:, mailto: etc.
+    if scheme || url.starts_with("//") {
+        url.into()
+    }
+    else if let Some(v) = url.strip_prefix('/') {
+        format!("{}/{}", prepend.trim_end_matches('/'), v)
+    }
+    else if url.is_empty() || url.starts_with(['#', '?']) {
+        format!("{}{}", page_url, url)
+    }
+    else { // relative to the page's directory
+        let mut dir = page_url.rfind('/').map(|i| &page_url[..=i]).unwrap_or(prepend);
+        let mut url = url;
+        loop { // resolve leading ./ and ../, but never above the site root
+            if let Some(rest) = url.strip_prefix("./") {
+                url = rest;
+            }
+            else if let Some(rest) = url.strip_prefix("../") {
+                if dir.len() > prepend.len() {
+                    dir = dir[..dir.len() - 1].rfind('/').map(|i| &dir[..=i]).filter(|d| d.len() >= prepend.len()).unwrap_or(prepend);
+                }
+                url = rest;
+            }
+            else {
+                break
+            }
+        }
+        format!("{}{}", dir, url)
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum RssError {
     #[error("rss item/channel with missing title")]
     MissingTitle,
-    #[error("rss item with malformed pubdate")]
+    #[error("rss item with malformed pubdate ({0})")]
     BadPubdate(#[from] chrono::ParseError),
-    #[error("rss item with non-existent channel id")]
+    #[error("rss item with non-existent channel id {0}")]
     ChannelNotFound(String),
     #[error("rss channel with missing prepend")]
     MissingPrepend,
@@ -129,4 +481,66 @@ pub enum RssError {
     MissingLink,
     #[error("rss channel with missing outfile")]
     MissingPath,
+    #[error("rss channel with an outfile outside the output dir: {0}")]
+    OutsideOutput(String),
+    #[error("couldn't write the feed for rss channel {0}: {1}")]
+    WriteFailed(String, String),
+    #[error("rss channel with a ttl that isn't a number of minutes: {0}")]
+    BadTtl(String),
+    #[error("rss item with an enclosure that isn't in the output dir: {0}")]
+    MissingEnclosure(String),
+    #[error("rss channel with a limit that isn't a number: {0}")]
+    BadLimit(String),
+    #[error("rss channel with unknown format {0} (should be rss, atom or json)")]
+    BadFormat(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_outputs_t() {
+        let mut cfg = HashMap::new();
+        cfg.insert("a".into(), "title=t\npath=blog\nprepend=/\nformat=rss,atom\noutfile=rss.xml\natom_outfile=atom.xml".into());
+        cfg.insert("b".into(), "title=t\npath=blog\nprepend=/\nformat=atom,json\n\noutfile=feed.xml\njson_outfile=feed.json\n".into());
+        let chans = get_channels(&cfg).unwrap();
+        assert_eq!(chans["a"].outputs, vec![(FeedFormat::Rss, "rss.xml".into()), (FeedFormat::Atom, "atom.xml".into())]);
+        assert_eq!(chans["b"].outputs, vec![(FeedFormat::Atom, "feed.xml".into()), (FeedFormat::Json, "feed.json".into())]);
+
+        // both formats can't share the one outfile
+        cfg.insert("c".into(), "title=t\npath=blog\nprepend=/\nformat=rss,atom\noutfile=feed.xml".into());
+        assert!(get_channels(&cfg).is_err());
+        cfg.insert("c".into(), "title=t\npath=blog\nprepend=/\noutfile=../feed.xml".into());
+        assert!(get_channels(&cfg).is_err());
+    }
+
+    #[test]
+    fn sort_items_t() {
+        let mut cfg = HashMap::new();
+        cfg.insert("a".into(), "title=t\npath=blog\nprepend=/\noutfile=rss.xml\nlimit=2".into());
+        let mut c = get_channels(&cfg).unwrap().remove("a").unwrap();
+        for (p, d) in [("a.md", None), ("b.md", Some("Tue, 1 Jul 2003 10:52:37 +0200")), ("c.md", Some("Wed, 2 Jul 2003 10:52:37 +0200"))] {
+            let mut fm = HashMap::new();
+            fm.insert("title".into(), p.into());
+            if let Some(d) = d {
+                fm.insert("rss_pubdate".into(), d.into());
+            }
+            c.items.push(RssItem::meta(&fm, Path::new(p), false).unwrap());
+        }
+        assert_eq!(c.sort_items("a").len(), 1);
+        assert_eq!(c.items.iter().map(|i| i.title.as_str()).collect::<Vec<_>>(), vec!["c.md", "b.md"]);
+        let built = DateTime::parse_from_rfc2822(c.c.last_build_date().unwrap()).unwrap(); // chrono versions pad the day differently
+        assert_eq!(built, DateTime::parse_from_rfc2822("Wed, 2 Jul 2003 10:52:37 +0200").unwrap());
+    }
+
+    #[test]
+    fn absolute_links_t() {
+        let html = "<a href=\"../index.html\">x</a> <img src='pic.png'> <a href=\"/a/b\"></a> <a href=\"https://x.org\"></a> <a href=\"#top\"></a> <p>href=\"nope\"</p>";
+        assert_eq!(
+            absolute_links(html, "https://e.com/blog/post1.html", "https://e.com/"),
+            "<a href=\"https://e.com/index.html\">x</a> <img src='https://e.com/blog/pic.png'> <a href=\"https://e.com/a/b\"></a> <a href=\"https://x.org\"></a> <a href=\"https://e.com/blog/post1.html#top\"></a> <p>href=\"nope\"</p>"
+        );
+        assert_eq!(html_body("<html><body class=\"a\">\n<p>hi</p>\n</body></html>"), "<p>hi</p>");
+    }
 }
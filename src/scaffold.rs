@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::fs::{write, create_dir_all};
+
+use chrono::Utc;
+
+use crate::proc::Dirs;
+use crate::utils::*;
+use crate::rss::RssError;
+
+const MAIN_TEMPLATE: &str = "<!doctype html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>##title##</title>
+<link rel=\"alternate\" type=\"application/rss+xml\" href=\"/blog/rss.xml\">
+</head>
+<body>
+<nav><a href=\"/index.html\">home</a> <a href=\"/blog/index.html\">blog</a></nav>
+##BODY##
+</body>
+</html>
+";
+
+const MD_REPLACE: &str = "site_name
+my new site";
+
+const CHANNELS: &str = "blog
+title=my new site
+description=posts from the new site
+path=blog/index.html
+outfile=blog/rss.xml
+prepend=https://example.com/
+";
+
+const INDEX_PAGE: &str = "---
+title=home
+---
+##BODY##
+# welcome to REP=site_name
+
+this page is `site/index.md`. there's a [blog](blog/index.html) too, with one post in it.
+
+run `staticcc serve` and edit away.
+";
+
+const BLOG_PAGE: &str = "---
+title=blog
+---
+##BODY##
+# blog
+
+##for:rss=blog##
+* [##item_title##](/##item_url##) ##item_pubdate##
+##endfor##
+";
+
+/// a small working site: a template, config, a home page, and a blog with a feed and one post
+pub fn init(dirs: &Dirs) -> Result<(), StcError> {
+    let post = format!("---
+title=hello world
+rss_chan_id=blog
+rss_pubdate={}
+rss_description=the first post
+---
+##BODY##
+this is `site/blog/hello-world.md`. anything with `rss_chan_id=blog` ends up in the blog's feed, newest first.
+", Utc::now().to_rfc2822());
+
+    let files = [
+        (dirs.cfg.join("templates").join("main"), MAIN_TEMPLATE),
+        (dirs.cfg.join("md_ignore"), ""),
+        (dirs.cfg.join("md_replace"), MD_REPLACE),
+        (dirs.cfg.join("channels"), CHANNELS),
+        (dirs.inp.join("index.md"), INDEX_PAGE),
+        (dirs.inp.join("blog").join("index.md"), BLOG_PAGE),
+        (dirs.inp.join("blog").join("hello-world.md"), &post),
+    ];
+    for (p, _) in &files { // check everything first, so we don't leave half a site behind
+        refuse_existing(p)?;
+    }
+    for (p, body) in &files {
+        create_file(p, body)?;
+    }
+    Ok(())
+}
+
+/// a blank page at `path`, relative to the input dir
+pub fn new_page(dirs: &Dirs, path: &Path, title: Option<&str>, template: Option<&str>) -> Result<(), StcError> {
+    let mut file = dirs.inp.join(path);
+    if file.extension().is_none() {
+        file.set_extension("md");
+    }
+    refuse_existing(&file)?;
+
+    let title = match title {
+        Some(v) => v.to_owned(),
+        None => file.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+    };
+    let mut doc = format!("---\ntitle={}\n", title);
+    if let Some(t) = template {
+        doc.push_str(&format!("template={}\n", t));
+    }
+    doc.push_str("---\n##BODY##\n");
+
+    create_file(&file, &doc)
+}
+
+/// a post in one of the channels from `cfg/channels`, named after its title
+pub fn new_post(dirs: &Dirs, title: &str, path: Option<&Path>, channel: Option<&str>, template: Option<&str>) -> Result<(), StcError> {
+    let cfg_file = dirs.cfg.join("channels");
+    let src = read_or_none(&cfg_file)?
+        .ok_or_else(|| StcError::CfgErr(format!("no channels to put a post in, {} doesn't exist", cfg_file.to_string_lossy())))?;
+    let channels = parse_shit_markup(src.trim_end()).map_err(|e| e.in_file(&cfg_file, &src))?;
+
+    let (id, body) = match channel {
+        Some(c) => channels.iter().find(|(id, _)| id == c).ok_or_else(|| RssError::ChannelNotFound(c.into()))?,
+        None if channels.len() == 1 => &channels[0],
+        None => {
+            let ids: Vec<_> = channels.iter().map(|(id, _)| id.as_str()).collect();
+            return Err(StcError::CfgErr(format!("more than one channel, pick one with --channel: {}", ids.join(", "))))
+        }
+    };
+
+    let dir = match path {
+        Some(v) => v.to_owned(),
+        None => { // next to the channel's page
+            let cfg = scf_to_hashmap(parse_singleline_scf(body.trim_end()).map_err(|e| e.near(id).in_file(&cfg_file, &src))?);
+            let p = PathBuf::from(cfg.get("path").map(|s| s.as_str()).unwrap_or(""));
+            match p.extension() {
+                Some(_) => p.parent().map(Path::to_owned).unwrap_or_default(),
+                None => p
+            }
+        }
+    };
+
+    let slug = slugify(title);
+    if slug.is_empty() {
+        return Err(StcError::CfgErr(format!("can't make a file name out of the title \"{}\"", title)))
+    }
+    let file = dirs.inp.join(dir).join(format!("{}.md", slug));
+    refuse_existing(&file)?;
+
+    let doc = format!("---\ntemplate={}\ntitle={}\nrss_chan_id={}\nrss_pubdate={}\n---\n##BODY##\n",
+        template.unwrap_or("main"), title, id, Utc::now().to_rfc2822());
+    create_file(&file, &doc)
+}
+
+/// lowercase, with every run of anything that isn't a letter or number turned into a single `-`
+pub fn slugify(s: &str) -> String {
+    let mut ret = String::new();
+    for c in s.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            ret.push(c);
+        }
+        else if !ret.is_empty() && !ret.ends_with('-') {
+            ret.push('-');
+        }
+    }
+    ret.trim_end_matches('-').into()
+}
+
+fn refuse_existing(p: &Path) -> Result<(), StcError> {
+    if p.exists() {
+        return Err(StcError::AlreadyExists(p.to_string_lossy().into()))
+    }
+    Ok(())
+}
+
+fn create_file(p: &Path, body: &str) -> Result<(), StcError> {
+    if let Some(parent) = p.parent() {
+        create_dir_all(parent)?;
+    }
+    write(p, body)?;
+    println!("created {}", p.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_t() {
+        let root = std::env::temp_dir().join(format!("stc_init_t_{}", std::process::id()));
+        let dirs = Dirs { inp: root.join("site"), out: root.join("build"), cfg: root.join("cfg") };
+
+        init(&dirs).unwrap();
+        assert!(dirs.inp.join("blog/hello-world.md").is_file());
+        std::fs::write(dirs.cfg.join("md_replace"), "changed").unwrap();
+        assert!(matches!(init(&dirs), Err(StcError::AlreadyExists(_))));
+        let kept = std::fs::read_to_string(dirs.cfg.join("md_replace")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(kept, "changed");
+    }
+
+    #[test]
+    fn slugify_t() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  -- rust 2024: what's new?"), "rust-2024-what-s-new");
+        assert_eq!(slugify("Ünïcode"), "ünïcode");
+        assert_eq!(slugify("!!!"), "");
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream};
+use std::io::{self, BufRead, BufReader, Write};
+use std::fs::read;
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::proc::{Processor, ProcOpts};
+use crate::walkdir::WalkDir;
+use crate::utils::{StcError, content_type, stays_inside};
+
+const RELOAD_PATH: &str = "/__staticcc/reload";
+const RELOAD_SCRIPT: &str = "<script>new EventSource(\"/__staticcc/reload\").onmessage = function() { location.reload(); };</script>";
+
+/// build generation counter. bumped after every rebuild, waited on by open reload streams
+#[derive(Default)]
+struct Reload {
+    gen: Mutex<u64>,
+    cv: Condvar,
+}
+impl Reload {
+    fn bump(&self) {
+        let mut g = self.gen.lock().unwrap();
+        *g += 1;
+        self.cv.notify_all();
+    }
+    fn current(&self) -> u64 {
+        *self.gen.lock().unwrap()
+    }
+    /// wait until the generation moves past `seen`, or the timeout runs out
+    fn wait_past(&self, seen: u64, timeout: Duration) -> u64 {
+        let g = self.gen.lock().unwrap();
+        let (g, _) = self.cv.wait_timeout_while(g, timeout, |g| *g == seen).unwrap();
+        *g
+    }
+}
+
+pub fn serve(po: &ProcOpts) -> Result<(), StcError> {
+    let dirs = po.dirs()?;
+    let port = po.port.unwrap_or(8000);
+
+    rebuild(po); // first build. errors are printed, not fatal, so the site can be fixed while serving
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("serving {} at http://127.0.0.1:{}/", dirs.out.to_string_lossy(), port);
+
+    let reload = Arc::new(Reload::default());
+
+    { // watcher thread
+        let reload = Arc::clone(&reload);
+        let po = po.clone();
+        let watched = vec![dirs.inp.clone(), dirs.cfg.clone()];
+        thread::spawn(move || {
+            let mut last = snapshot(&watched);
+            loop {
+                thread::sleep(Duration::from_millis(500));
+                let now = snapshot(&watched);
+                if now != last {
+                    println!("change detected, rebuilding");
+                    rebuild(&po);
+                    reload.bump();
+                    last = now;
+                }
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(v) => v,
+            Err(_) => continue // client hung up before we got to it
+        };
+        let reload = Arc::clone(&reload);
+        let out_dir = dirs.out.clone();
+        thread::spawn(move || {
+            let _ = handle_conn(stream, &out_dir, &reload); // a dropped connection isn't our problem
+        });
+    }
+
+    Ok(())
+}
+
+fn rebuild(po: &ProcOpts) {
+    let fresh = po.reload().map(|mut fresh| { // cfg/staticcc may have changed. the dirs and port can't, we're already watching and serving them
+        fresh.inp_dir = po.inp_dir.clone();
+        fresh.out_dir = po.out_dir.clone();
+        fresh
+    });
+    match fresh.and_then(|po| Processor::new(&po)?.build()) {
+        Ok(r) if r.errors.is_empty() && r.warnings.is_empty() => println!("build finished"),
+        Ok(r) => println!("build finished\n{}", r.summary()),
+        Err(e) => println!("build failed\n{}", e.diagnostic())
+    }
+}
+
+/// modification times of everything under the given directories
+fn snapshot(dirs: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut ret = HashMap::new();
+    for d in dirs {
+        let wd = match WalkDir::new(d) {
+            Ok(v) => v,
+            Err(_) => continue // missing dir, nothing to watch yet
+        };
+        for entry in wd.flatten() {
+            let full = d.join(entry);
+            if let Ok(t) = full.metadata().and_then(|m| m.modified()) {
+                ret.insert(full, t);
+            }
+        }
+    }
+    ret
+}
+
+fn handle_conn(stream: TcpStream, out_dir: &Path, reload: &Reload) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop { // skip headers, we don't need any of them
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let path = target.split(['?', '#']).next().unwrap_or("/");
+
+    let mut stream = stream;
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed", false)
+    }
+    if path == RELOAD_PATH {
+        return reload_stream(stream, reload)
+    }
+
+    match resolve_file(out_dir, path) {
+        Some(file) => {
+            let mut body = read(&file)?;
+            let ctype = content_type(&file);
+            if ctype.starts_with("text/html") {
+                body = inject_script(body);
+            }
+            respond(&mut stream, "200 OK", ctype, &body, method == "HEAD")
+        }
+        None => respond(&mut stream, "404 Not Found", "text/plain", b"not found", method == "HEAD")
+    }
+}
+
+/// server-sent events stream that says "reload" whenever a build finishes
+fn reload_stream(mut stream: TcpStream, reload: &Reload) -> io::Result<()> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n")?;
+    stream.flush()?;
+    let mut seen = reload.current();
+    loop {
+        let now = reload.wait_past(seen, Duration::from_secs(15));
+        if now != seen {
+            seen = now;
+            stream.write_all(b"data: reload\n\n")?;
+        }
+        else {
+            stream.write_all(b": ping\n\n")?; // keepalive, also how we notice the tab went away
+        }
+        stream.flush()?;
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, ctype: &str, body: &[u8], head_only: bool) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n", status, ctype, body.len())?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+/// map a request path onto a file in the output dir. never leaves the output dir
+fn resolve_file(out_dir: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(path)?;
+    let rel = Path::new(decoded.trim_start_matches('/'));
+    if !stays_inside(rel) {
+        return None
+    }
+    let mut file = out_dir.join(rel);
+    if file.is_dir() {
+        file = file.join("index.html");
+    }
+    if file.is_file() {
+        Some(file)
+    }
+    else {
+        None
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let b = s.as_bytes();
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let hex = std::str::from_utf8(b.get(i + 1..i + 3)?).ok()?;
+            ret.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        }
+        else {
+            ret.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(ret).ok()
+}
+
+fn inject_script(body: Vec<u8>) -> Vec<u8> {
+    let html = String::from_utf8_lossy(&body);
+    match html.rfind("</body>") {
+        Some(idx) => {
+            let mut ret = String::from(&html[..idx]);
+            ret.push_str(RELOAD_SCRIPT);
+            ret.push_str(&html[idx..]);
+            ret.into_bytes()
+        }
+        None => { // no body tag, tack it on the end and hope for the best
+            let mut ret = html.into_owned();
+            ret.push_str(RELOAD_SCRIPT);
+            ret.into_bytes()
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::fs::create_dir_all;
+use std::time::SystemTime;
+use chrono::{DateTime, NaiveDate, Utc};
+use thiserror::Error;
+
+use crate::utils::*;
+
+const CHANGEFREQS: [&str; 7] = ["always", "hourly", "daily", "weekly", "monthly", "yearly", "never"];
+
+/// one `<url>` in the sitemap
+#[derive(Debug, Clone)]
+pub struct SitemapUrl {
+    loc: String, // relative to site root
+    lastmod: Option<String>, // already in w3c datetime format
+    changefreq: Option<String>,
+    priority: Option<String>,
+}
+
+impl SitemapUrl {
+    /// None if the page opted out with `sitemap=false`
+    pub fn new(front_matter: &HashMap<String, String>, url: &str, mtime: Option<SystemTime>) -> Result<Option<SitemapUrl>, StcError> {
+        if front_matter.get("sitemap").map(|v| v.trim() == "false").unwrap_or(false) {
+            return Ok(None)
+        }
+
+        let lastmod = match front_matter.get("sitemap_lastmod") {
+            Some(v) => Some(parse_lastmod(v)?),
+            None => mtime.map(|t| DateTime::<Utc>::from(t).format("%Y-%m-%dT%H:%M:%S+00:00").to_string())
+        };
+        let changefreq = match front_matter.get("sitemap_changefreq") {
+            Some(v) if CHANGEFREQS.contains(&v.as_str()) => Some(v.to_owned()),
+            Some(v) => return Err(SitemapError::BadChangefreq(v.to_owned()).into()),
+            None => None
+        };
+        let priority = match front_matter.get("sitemap_priority") {
+            Some(v) => match v.parse::<f32>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Some(v.to_owned()),
+                _ => return Err(SitemapError::BadPriority(v.to_owned()).into())
+            },
+            None => None
+        };
+
+        Ok(Some(SitemapUrl {
+            loc: url.into(), lastmod, changefreq, priority
+        }))
+    }
+}
+
+/// a date (`2022-06-01`), rfc 3339 or rfc 2822 datetime, as a w3c datetime
+fn parse_lastmod(s: &str) -> Result<String, StcError> {
+    let s = s.trim();
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(d.format("%Y-%m-%d").to_string())
+    }
+    let dt = DateTime::parse_from_rfc3339(s)
+        .or_else(|_| DateTime::parse_from_rfc2822(s))
+        .map_err(|_| SitemapError::BadLastmod(s.into()))?;
+    Ok(dt.to_rfc3339())
+}
+
+/// the `cfg/sitemap` file
+#[derive(Debug)]
+pub struct SitemapCfg {
+    pub prepend: String,
+    pub out_file: PathBuf,
+}
+impl SitemapCfg {
+    pub fn new(cfg: &HashMap<String, String>) -> Result<SitemapCfg, StcError> {
+        let prepend = cfg.get("prepend").ok_or(SitemapError::MissingPrepend)?;
+        let out_file = PathBuf::from(cfg.get("outfile").map(|s| s.as_str()).unwrap_or("sitemap.xml"));
+        if !stays_inside(&out_file) {
+            return Err(SitemapError::OutsideOutput(out_file.to_string_lossy().into()).into())
+        }
+        Ok(SitemapCfg {
+            prepend: prepend.to_owned(),
+            out_file,
+        })
+    }
+
+    pub fn write(&self, urls: &[SitemapUrl], out_dir: &Path) -> Result<(), StcError> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+        for u in urls {
+            xml.push_str("<url>");
+            xml.push_str(&format!("<loc>{}</loc>", xml_escape(&format!("{}{}", self.prepend, u.loc))));
+            if let Some(v) = &u.lastmod {
+                xml.push_str(&format!("<lastmod>{}</lastmod>", v));
+            }
+            if let Some(v) = &u.changefreq {
+                xml.push_str(&format!("<changefreq>{}</changefreq>", v));
+            }
+            if let Some(v) = &u.priority {
+                xml.push_str(&format!("<priority>{}</priority>", v));
+            }
+            xml.push_str("</url>\n");
+        }
+        xml.push_str("</urlset>\n");
+
+        let out_path = out_dir.join(&self.out_file);
+        if let Some(parent) = out_path.parent() {
+            create_dir_all(parent)?;
+        }
+        write_fresh(out_path, xml)?;
+        Ok(())
+    }
+}
+
+pub fn xml_escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            _ => ret.push(c)
+        }
+    }
+    ret
+}
+
+#[derive(Error, Debug)]
+pub enum SitemapError {
+    #[error("sitemap config with missing prepend")]
+    MissingPrepend,
+    #[error("page with a sitemap_changefreq that isn't always/hourly/daily/weekly/monthly/yearly/never: {0}")]
+    BadChangefreq(String),
+    #[error("page with a sitemap_priority that isn't between 0.0 and 1.0: {0}")]
+    BadPriority(String),
+    #[error("page with malformed sitemap_lastmod: {0}")]
+    BadLastmod(String),
+    #[error("sitemap config with an outfile outside the output dir: {0}")]
+    OutsideOutput(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sitemap_url_t() {
+        let mut fm = HashMap::new();
+        fm.insert("sitemap_lastmod".into(), "Tue, 1 Jul 2003 10:52:37 +0200".into());
+        fm.insert("sitemap_priority".into(), "0.5".into());
+        let u = SitemapUrl::new(&fm, "a/", None).unwrap().unwrap();
+        assert_eq!(u.lastmod.as_deref(), Some("2003-07-01T10:52:37+02:00"));
+
+        fm.insert("sitemap_priority".into(), "2".into());
+        assert!(SitemapUrl::new(&fm, "a/", None).is_err());
+        assert!(SitemapUrl::new(&HashMap::new(), "a/", None).unwrap().is_some());
+        fm.insert("sitemap".into(), "false".into());
+        assert!(SitemapUrl::new(&fm, "a/", None).unwrap().is_none());
+    }
+
+    #[test]
+    fn sitemap_cfg_t() {
+        let mut cfg = HashMap::new();
+        cfg.insert("prepend".into(), "/".into());
+        cfg.insert("outfile".into(), "maps/sitemap.xml".into());
+        assert!(SitemapCfg::new(&cfg).is_ok());
+        cfg.insert("outfile".into(), "../x.xml".into());
+        assert!(SitemapCfg::new(&cfg).is_err());
+    }
+}
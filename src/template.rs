@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::utils::*;
+use crate::rss::RssItem;
+
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Text(String),
+    Slot(String), // ##NAME##
+    Block(String, Vec<Token>), // ##block:NAME## ... ##endblock##, overridable by child templates
+    If(Cond, Vec<Token>, Vec<Token>), // ##if:COND## ... ##else## ... ##endif##
+    For(Query, Vec<Token>), // ##for:QUERY## ... ##endfor##
+}
+
+/// `KEY` (present and not blank) or `KEY=value` (equal, ignoring surrounding whitespace)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cond {
+    pub key: String,
+    pub value: Option<String>,
+}
+impl Cond {
+    fn parse(s: &str) -> Cond {
+        match s.split_once('=') {
+            Some((k, v)) => Cond { key: k.trim().into(), value: Some(v.trim().into()) },
+            None => Cond { key: s.trim().into(), value: None }
+        }
+    }
+
+    fn holds(&self, scope: &Scope) -> bool {
+        match (scope.get(&self.key).map(|v| v.trim()), &self.value) {
+            (Some(v), None) => !v.is_empty(),
+            (Some(v), Some(want)) => v == want,
+            (None, _) => false
+        }
+    }
+}
+
+/// how a page goes from markdown to html
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderMode {
+    /// each section is rendered to html on its own, then put into the template as-is
+    #[default]
+    Sections,
+    /// sections are put into the template, then the whole thing is rendered as markdown
+    Legacy,
+}
+impl RenderMode {
+    pub fn parse(s: &str) -> Result<RenderMode, StcError> {
+        match s.trim() {
+            "sections" => Ok(RenderMode::Sections),
+            "legacy" => Ok(RenderMode::Legacy),
+            other => Err(StcError::CfgErr(format!("render_mode must be sections or legacy, not {}", other)))
+        }
+    }
+}
+
+/// what a `##for:...##` loops over
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Rss(String), // pages in a channel
+    Dir(PathBuf), // pages under a directory, relative to the input dir
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Pubdate,
+    Title,
+    Url,
+}
+/// `rss=ID` or `dir=PATH`, then optionally `;sort=pubdate|title|url`, `;order=asc|desc` and `;limit=N`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub source: Source,
+    pub sort: SortKey,
+    pub desc: bool,
+    pub limit: Option<usize>,
+}
+impl Query {
+    fn parse(s: &str) -> Result<Query, String> {
+        let mut source = None;
+        let mut sort = SortKey::Pubdate;
+        let mut order = None;
+        let mut limit = None;
+        for part in s.split(';') {
+            let (k, v) = part.split_once('=').ok_or_else(|| format!("expected key=value in loop, got {}", part))?;
+            let v = v.trim();
+            match k.trim() {
+                "rss" => source = Some(Source::Rss(v.into())),
+                "dir" => source = Some(Source::Dir(PathBuf::from(v.trim_matches('/')))),
+                "sort" => sort = match v {
+                    "pubdate" => SortKey::Pubdate,
+                    "title" => SortKey::Title,
+                    "url" => SortKey::Url,
+                    _ => return Err(format!("can't sort loop by {}", v))
+                },
+                "order" => order = match v {
+                    "asc" => Some(false),
+                    "desc" => Some(true),
+                    _ => return Err(format!("loop order must be asc or desc, not {}", v))
+                },
+                "limit" => limit = Some(v.parse().map_err(|_| format!("bad loop limit {}", v))?),
+                other => return Err(format!("unknown loop option {}", other))
+            }
+        }
+
+        Ok(Query {
+            source: source.ok_or("loop needs an rss= or dir= source")?,
+            sort,
+            desc: order.unwrap_or(sort == SortKey::Pubdate), // newest first, otherwise alphabetical
+            limit,
+        })
+    }
+}
+
+/// a markdown page, as seen by loops in other pages
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub path: PathBuf, // relative to the input dir
+    pub channels: Vec<String>,
+    pub meta: RssItem,
+    pub fields: HashMap<String, String>, // what `##item_...##` tags get filled with
+}
+impl Page {
+    pub fn new(path: PathBuf, channels: Vec<String>, meta: RssItem) -> Page {
+        let mut fields = HashMap::new();
+        fields.insert("item_title".into(), meta.title.clone());
+        fields.insert("item_url".into(), meta.page.clone());
+        fields.insert("item_description".into(), meta.description.clone());
+        if let Some(d) = meta.pubdate {
+            fields.insert("item_pubdate".into(), d.format("%Y-%m-%d").to_string());
+        }
+        Page {
+            path, channels, meta, fields
+        }
+    }
+}
+
+/// the pages loops can pick from, minus the one being rendered
+#[derive(Debug, Clone, Copy)]
+pub struct Listing<'a> {
+    pub pages: &'a [Page],
+    pub current: &'a Path,
+}
+impl Default for Listing<'_> {
+    fn default() -> Self {
+        Listing {
+            pages: &[], current: Path::new("")
+        }
+    }
+}
+impl<'a> Listing<'a> {
+    fn select(&self, q: &Query) -> Vec<&'a Page> {
+        let mut ret: Vec<&Page> = self.pages.iter().filter(|p| p.path != self.current && match &q.source {
+            Source::Rss(id) => p.channels.contains(id),
+            Source::Dir(d) => p.path.starts_with(d),
+        }).collect();
+        ret.sort_by(|a, b| { // stable, so ties stay in walk order
+            let (a, b) = if q.desc { (b, a) } else { (a, b) };
+            match q.sort {
+                SortKey::Pubdate => a.meta.pubdate.cmp(&b.meta.pubdate),
+                SortKey::Title => a.meta.title.cmp(&b.meta.title),
+                SortKey::Url => a.meta.page.cmp(&b.meta.page),
+            }
+        });
+        if let Some(l) = q.limit {
+            ret.truncate(l);
+        }
+        ret
+    }
+}
+
+/// where slots get their values from. earlier maps shadow later ones
+#[derive(Debug, Clone, Default)]
+pub struct Scope<'a> {
+    vars: Vec<&'a HashMap<String, String>>,
+}
+impl<'a> Scope<'a> {
+    pub fn new(vars: Vec<&'a HashMap<String, String>>) -> Scope<'a> {
+        Scope { vars }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.vars.iter().find_map(|m| m.get(name)).map(|v| v.as_str())
+    }
+
+    /// a scope where `inner` shadows everything in this one
+    fn with(&self, inner: &'a HashMap<String, String>) -> Scope<'a> {
+        let mut vars = vec![inner];
+        vars.extend(self.vars.iter());
+        Scope { vars }
+    }
+}
+
+/// +1 for tags that open a directive, -1 for tags that close one, 0 for everything else
+pub fn nesting(tag: &str) -> i32 {
+    if tag.starts_with("block:") || tag.starts_with("if:") || tag.starts_with("for:") {
+        1
+    }
+    else if tag == "endblock" || tag == "endif" || tag == "endfor" {
+        -1
+    }
+    else {
+        0
+    }
+}
+
+/// true if the tag means something to the template engine, rather than being a slot or section name
+pub fn is_directive(tag: &str) -> bool {
+    nesting(tag) != 0 || tag == "else" || tag.starts_with("extends:") || tag.starts_with("include:") || tag.starts_with("render_mode:")
+}
+
+/// something we're in the middle of parsing
+enum Frame<'a> {
+    Root,
+    Block(&'a str),
+    If(Cond, Option<Vec<Token>>), // the then branch, once we've hit an else
+    For(Query),
+}
+impl Frame<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Frame::Root => "the top level".into(),
+            Frame::Block(b) => format!("block {}", b),
+            Frame::If(c, _) => format!("if {}", c.key),
+            Frame::For(_) => "for".into(),
+        }
+    }
+}
+
+/// a template, parsed once into a tree of literal text, `##NAME##` slots and blocks
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub extends: Option<String>, // parent template, None once resolved
+    pub render_mode: Option<RenderMode>, // ##render_mode:...##, if the template (or its parents) set one
+    pub tokens: Vec<Token>,
+    pub hash: u64, // of the source (and its parents, once resolved), for the build manifest
+}
+
+impl Template {
+    /// `name` is only used for error messages
+    pub fn parse(name: &str, src: &str) -> Result<Template, StcError> {
+        let mut extends = None;
+        let mut render_mode = None;
+        let mut stack: Vec<(Frame, Vec<Token>)> = vec![(Frame::Root, Vec::new())]; // open blocks and ifs, innermost last
+        let mut text_start = 0; // start of the text we haven't tokenised yet
+        for (start, end, tag) in find_tags(src) {
+            let top = &mut stack.last_mut().unwrap().1; // never empty, the root can't be closed
+            if start > text_start {
+                top.push(Token::Text(src[text_start..start].into()));
+            }
+            text_start = end;
+
+            if let Some(parent) = tag.strip_prefix("extends:") {
+                extends = Some(String::from(parent.trim()));
+            }
+            else if let Some(mode) = tag.strip_prefix("render_mode:") {
+                render_mode = Some(RenderMode::parse(mode).map_err(|_| StcError::TemplateError(format!("{}: bad render mode {}", name, mode)))?);
+            }
+            else if let Some(block) = tag.strip_prefix("block:") {
+                stack.push((Frame::Block(block.trim()), Vec::new()));
+            }
+            else if let Some(cond) = tag.strip_prefix("if:") {
+                stack.push((Frame::If(Cond::parse(cond), None), Vec::new()));
+            }
+            else if let Some(query) = tag.strip_prefix("for:") {
+                let q = Query::parse(query).map_err(|e| StcError::TemplateError(format!("{}: {}", name, e)))?;
+                stack.push((Frame::For(q), Vec::new()));
+            }
+            else if tag == "else" {
+                match stack.last_mut().unwrap() {
+                    (Frame::If(_, then @ None), tokens) => *then = Some(std::mem::take(tokens)),
+                    (f, _) => return Err(StcError::TemplateError(format!("{}: else in {}", name, f.describe())))
+                }
+            }
+            else if tag == "endblock" || tag == "endif" || tag == "endfor" {
+                let (frame, tokens) = stack.pop().unwrap();
+                let t = match (frame, tag) {
+                    (Frame::Block(b), "endblock") => Token::Block(b.into(), tokens),
+                    (Frame::If(c, None), "endif") => Token::If(c, tokens, Vec::new()),
+                    (Frame::If(c, Some(then)), "endif") => Token::If(c, then, tokens),
+                    (Frame::For(q), "endfor") => Token::For(q, tokens),
+                    (f, _) => return Err(StcError::TemplateError(format!("{}: {} in {}", name, tag, f.describe())))
+                };
+                stack.last_mut().unwrap().1.push(t);
+            }
+            else {
+                top.push(Token::Slot(tag.into()));
+            }
+        }
+        if stack.len() > 1 {
+            return Err(StcError::TemplateError(format!("{}: {} is never closed", name, stack.last().unwrap().0.describe())))
+        }
+        let mut tokens = stack.pop().unwrap().1;
+        if text_start < src.len() {
+            tokens.push(Token::Text(src[text_start..].into()));
+        }
+
+        Ok(Template {
+            extends, render_mode, tokens, hash: stable_hash(&src)
+        })
+    }
+
+    /// fill in slots from `scope` (or with nothing), run loops over `listing`, and make replacements, in one pass
+    pub fn render(&self, scope: &Scope, listing: &Listing, reps: &Replacements) -> String {
+        let mut out = String::new();
+        render_tokens(&self.tokens, scope, listing, reps, &mut out);
+        out
+    }
+
+    /// true if the output depends on other pages, not just this one
+    pub fn uses_listing(&self) -> bool {
+        fn any_for(tokens: &[Token]) -> bool {
+            tokens.iter().any(|t| match t {
+                Token::For(..) => true,
+                Token::Block(_, inner) => any_for(inner),
+                Token::If(_, then, otherwise) => any_for(then) || any_for(otherwise),
+                _ => false
+            })
+        }
+        any_for(&self.tokens)
+    }
+}
+
+fn render_tokens(tokens: &[Token], scope: &Scope, listing: &Listing, reps: &Replacements, out: &mut String) {
+    for t in tokens {
+        match t {
+            Token::Text(s) => reps.expand_into(s, out),
+            Token::Slot(name) => {
+                if let Some(v) = scope.get(name) {
+                    reps.expand_into(v, out)
+                } // unused tags just disappear
+            }
+            Token::Block(_, inner) => render_tokens(inner, scope, listing, reps, out),
+            Token::If(cond, then, otherwise) => {
+                if cond.holds(scope) {
+                    render_tokens(then, scope, listing, reps, out)
+                }
+                else {
+                    render_tokens(otherwise, scope, listing, reps, out)
+                }
+            }
+            Token::For(q, body) => {
+                for p in listing.select(q) {
+                    render_tokens(body, &scope.with(&p.fields), listing, reps, out)
+                }
+            }
+        }
+    }
+}
+
+/// resolve inheritance for every template, so each one ends up self-contained
+pub fn resolve_all(raw: &HashMap<String, Template>) -> Result<HashMap<String, Template>, StcError> {
+    let mut ret = HashMap::new();
+    for name in raw.keys() {
+        let t = resolve(name, raw, &mut Vec::new())?;
+        ret.insert(name.clone(), t);
+    }
+    Ok(ret)
+}
+
+/// `chain` is the templates we're in the middle of resolving, for cycle detection
+fn resolve(name: &str, raw: &HashMap<String, Template>, chain: &mut Vec<String>) -> Result<Template, StcError> {
+    let t = raw.get(name).ok_or_else(|| StcError::TemplateError(name.into()))?;
+    let parent_name = match &t.extends {
+        None => return Ok(t.clone()),
+        Some(v) => v
+    };
+
+    chain.push(name.into());
+    if chain.contains(parent_name) {
+        chain.push(parent_name.clone());
+        return Err(StcError::TemplateError(format!("template inheritance cycle: {}", chain.join(" -> "))))
+    }
+    if !raw.contains_key(parent_name) {
+        return Err(StcError::TemplateError(format!("{} extends {}, which doesn't exist", name, parent_name)))
+    }
+    let parent = resolve(parent_name, raw, chain)?;
+    chain.pop();
+
+    let mut overrides = HashMap::new();
+    collect_blocks(&t.tokens, &mut overrides);
+    Ok(Template {
+        extends: None,
+        render_mode: t.render_mode.or(parent.render_mode), // children win
+        tokens: override_blocks(&parent.tokens, &overrides),
+        hash: stable_hash(&(t.hash, parent.hash)),
+    })
+}
+
+/// every block in the tree, nested ones included
+fn collect_blocks<'a>(tokens: &'a [Token], ret: &mut HashMap<&'a str, &'a Vec<Token>>) {
+    for t in tokens {
+        match t {
+            Token::Block(name, inner) => {
+                ret.insert(name, inner);
+                collect_blocks(inner, ret);
+            }
+            Token::If(_, then, otherwise) => {
+                collect_blocks(then, ret);
+                collect_blocks(otherwise, ret);
+            }
+            Token::For(_, body) => collect_blocks(body, ret),
+            _ => {}
+        }
+    }
+}
+
+fn override_blocks(tokens: &[Token], overrides: &HashMap<&str, &Vec<Token>>) -> Vec<Token> {
+    tokens.iter().map(|t| match t {
+        Token::Block(name, inner) => match overrides.get(name.as_str()) {
+            Some(new) => Token::Block(name.clone(), (*new).clone()), // kept as a block so grandchildren can override it too
+            None => Token::Block(name.clone(), override_blocks(inner, overrides))
+        },
+        Token::If(cond, then, otherwise) => Token::If(cond.clone(), override_blocks(then, overrides), override_blocks(otherwise, overrides)),
+        Token::For(q, body) => Token::For(q.clone(), override_blocks(body, overrides)),
+        other => other.clone()
+    }).collect()
+}
+
+/// paste partials in wherever there's an `##include:NAME##`, recursively. `from` is only used for error messages
+pub fn expand_includes(from: &str, src: &str, partials: &HashMap<String, String>) -> Result<String, StcError> {
+    expand_includes_inner(from, src, partials, &mut Vec::new())
+}
+
+/// `chain` is the partials we're currently inside of, outermost first
+fn expand_includes_inner(from: &str, src: &str, partials: &HashMap<String, String>, chain: &mut Vec<String>) -> Result<String, StcError> {
+    let mut out = String::new();
+    let mut copied = 0; // everything before this has been pushed already
+    for (start, end, tag) in find_tags(src) {
+        let name = match tag.strip_prefix("include:") {
+            Some(v) => v.trim(),
+            None => continue
+        };
+        if chain.iter().any(|c| c == name) {
+            chain.push(name.into());
+            return Err(StcError::IncludeError(format!("{}: include cycle: {}", from, chain.join(" -> "))))
+        }
+        if chain.len() >= MAX_INCLUDE_DEPTH {
+            return Err(StcError::IncludeError(format!("{}: includes nested more than {} deep at {}", from, MAX_INCLUDE_DEPTH, name)))
+        }
+        let partial = partials.get(name).ok_or_else(|| StcError::IncludeError(format!("{}: no partial named {}", from, name)))?;
+
+        out.push_str(&src[copied..start]);
+        chain.push(name.into());
+        out.push_str(&expand_includes_inner(from, partial, partials, chain)?);
+        chain.pop();
+        copied = end;
+    }
+    out.push_str(&src[copied..]);
+    Ok(out)
+}
+
+/// the `md_replace` replacements, ready to be expanded
+#[derive(Debug, Default, Clone)]
+pub struct Replacements {
+    reps: HashMap<String, String>,
+    keys: Vec<String>, // longest first, so `REP=ab` wins over `REP=a`
+}
+impl Replacements {
+    pub fn new(reps: HashMap<String, String>) -> Replacements {
+        let mut keys: Vec<String> = reps.keys().cloned().collect();
+        keys.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Replacements {
+            reps, keys
+        }
+    }
+
+    pub fn map(&self) -> &HashMap<String, String> {
+        &self.reps
+    }
+
+    /// replace every unescaped `REP=key` in `s` with its value, pushing the result onto `out`
+    pub fn expand_into(&self, s: &str, out: &mut String) {
+        let mut copied = 0; // everything before this has been pushed already
+        for idx in find_all_unescaped(s, "REP=") {
+            if idx < copied {
+                continue // inside a key we already replaced
+            }
+            let rest = &s[idx + 4..];
+            if let Some(k) = self.keys.iter().find(|k| rest.starts_with(k.as_str())) {
+                out.push_str(&s[copied..idx]);
+                out.push_str(&self.reps[k]);
+                copied = idx + 4 + k.len();
+            }
+        }
+        out.push_str(&s[copied..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn map(kvs: &[(&str, &str)]) -> HashMap<String, String> {
+        kvs.iter().map(|(k, v)| (String::from(*k), String::from(*v))).collect()
+    }
+
+    fn page(path: &str, title: &str, day: Option<u32>, chan: &str) -> Page {
+        let meta = RssItem {
+            page: path.replace(".md", ".html"),
+            title: title.into(),
+            description: String::new(),
+            pubdate: day.map(|d| format!("2022-01-{:02}T00:00:00Z", d).parse::<DateTime<Utc>>().unwrap()),
+            ..Default::default()
+        };
+        Page::new(path.into(), vec![chan.into()], meta)
+    }
+
+    #[test]
+    fn loops_t() {
+        let pages = vec![
+            page("blog/a.md", "a", Some(1), "feed"),
+            page("blog/b.md", "b", Some(3), "feed"),
+            page("blog/c.md", "c", None, "other"),
+            page("blog/index.md", "index", Some(2), "feed"),
+            page("about.md", "about", Some(4), "feed"),
+        ];
+        let listing = Listing { pages: &pages, current: Path::new("blog/index.md") };
+        let vars = map(&[("title", "page")]);
+        let scope = Scope::new(vec![&vars]);
+        let reps = Replacements::default();
+
+        let t = Template::parse("t", "##for:rss=feed##[##item_title## ##item_pubdate## ##title##]##endfor##").unwrap();
+        assert!(t.uses_listing());
+        assert_eq!(t.render(&scope, &listing, &reps), "[about 2022-01-04 page][b 2022-01-03 page][a 2022-01-01 page]");
+
+        let t = Template::parse("t", "##for:dir=blog/;sort=title;limit=2####item_url##,##endfor##").unwrap();
+        assert_eq!(t.render(&scope, &listing, &reps), "blog/a.html,blog/b.html,");
+
+        assert!(Template::parse("t", "##for:sort=title####endfor##").is_err());
+        assert!(Template::parse("t", "##for:rss=x;limit=lots####endfor##").is_err());
+    }
+
+    #[test]
+    fn render_t() {
+        let t = Template::parse("t", "test string ##HEAD## \\##HEAD## test ##HEAD## padding ##GONE##").unwrap();
+        let vars = map(&[("HEAD", "aaa")]);
+        let out = t.render(&Scope::new(vec![&vars]), &Listing::default(), &Replacements::default());
+        assert_eq!(out, "test string aaa \\##HEAD## test aaa padding ")
+    }
+
+    #[test]
+    fn parse_t() {
+        let t = Template::parse("t", "##A##b##\n##C##").unwrap();
+        assert_eq!(t.tokens, vec![
+            Token::Slot("A".into()),
+            Token::Text("b##\n".into()),
+            Token::Slot("C".into()),
+        ]);
+    }
+
+    #[test]
+    fn inheritance_t() {
+        let mut raw = HashMap::new();
+        raw.insert("main".into(), Template::parse("main", "<h>##block:head##default##endblock##</h>##block:body####BODY####endblock##").unwrap());
+        raw.insert("blog".into(), Template::parse("blog", "##extends:main####block:body##blog ##BODY####endblock##").unwrap());
+        raw.insert("post".into(), Template::parse("post", "##extends:blog####block:head##post##endblock##").unwrap());
+        let resolved = resolve_all(&raw).unwrap();
+        let vars = map(&[("BODY", "x")]);
+        let out = resolved["post"].render(&Scope::new(vec![&vars]), &Listing::default(), &Replacements::default());
+        assert_eq!(out, "<h>post</h>blog x");
+
+        raw.insert("main".into(), Template::parse("main", "##extends:post##").unwrap());
+        assert!(matches!(resolve_all(&raw), Err(StcError::TemplateError(_))));
+        raw.insert("main".into(), Template::parse("main", "##extends:nope##").unwrap());
+        assert!(matches!(resolve_all(&raw), Err(StcError::TemplateError(_))));
+    }
+
+    #[test]
+    fn conditionals_t() {
+        let t = Template::parse("t", "##if:FOOT##<f>##FOOT##</f>##endif####if:lang=fr##bonjour##else##hello##endif##").unwrap();
+        let vars = map(&[("FOOT", "  \n"), ("lang", "fr")]);
+        let out = t.render(&Scope::new(vec![&vars]), &Listing::default(), &Replacements::default());
+        assert_eq!(out, "bonjour");
+        let vars = map(&[("FOOT", "x")]);
+        let out = t.render(&Scope::new(vec![&vars]), &Listing::default(), &Replacements::default());
+        assert_eq!(out, "<f>x</f>hello");
+
+        assert!(Template::parse("t", "##if:a####endblock##").is_err());
+        assert!(Template::parse("t", "##if:a####else####else####endif##").is_err());
+        assert!(Template::parse("t", "##if:a##").is_err());
+    }
+
+    #[test]
+    fn includes_t() {
+        let mut partials = HashMap::new();
+        partials.insert("nav".into(), "<nav>##include:links##</nav>".into());
+        partials.insert("links".into(), "a b".into());
+        let out = expand_includes("t", "x ##include:nav## \\##include:nav## ##TITLE##", &partials).unwrap();
+        assert_eq!(out, "x <nav>a b</nav> \\##include:nav## ##TITLE##");
+
+        partials.insert("links".into(), "##include:nav##".into());
+        assert!(matches!(expand_includes("t", "##include:nav##", &partials), Err(StcError::IncludeError(_))));
+        assert!(matches!(expand_includes("t", "##include:nope##", &partials), Err(StcError::IncludeError(_))));
+    }
+
+    #[test]
+    fn reps_t() {
+        let mut m = HashMap::new();
+        m.insert("a".into(), "short".into());
+        m.insert("ab".into(), "long".into());
+        let r = Replacements::new(m);
+        let mut out = String::new();
+        r.expand_into("x REP=ab REP=a \\REP=a REP=c", &mut out);
+        assert_eq!(out, "x long short \\REP=a REP=c")
+    }
+}
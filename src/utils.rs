@@ -1,12 +1,14 @@
 use std::collections::HashMap;
-use std::fs::read_to_string;
+use std::fs::{self, read_to_string};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf, Component};
 use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
 
 use thiserror::Error;
-use fancy_regex::Regex;
 use crate::rss::RssError;
+use crate::sitemap::SitemapError;
+use crate::template::{is_directive, nesting};
 
 pub fn find_all_unescaped(s: &str, pat: &str) -> Vec<usize> {
     let mat = s.match_indices(pat);
@@ -18,86 +20,109 @@ pub fn find_all_unescaped(s: &str, pat: &str) -> Vec<usize> {
     let b = s.as_bytes(); // allow indexing
     let mut ret2 = Vec::new();
     for mat in ret1 { // check every match
-        if mat > 0 {
-            if b[mat - 1] != b'\\' { // if it's unescaped, add idx to ret
-                ret2.push(mat)
-            }
+        if mat == 0 || b[mat - 1] != b'\\' { // if it's unescaped, add idx to ret
+            ret2.push(mat)
         }
     }
 
     ret2
 }
 
-pub fn replace_all_unescaped(s: &str, pat: &str, rep: &str) -> String {
-    let mut ret = String::from(s);
-    loop {
-        let idxs = find_all_unescaped(&ret, pat);
-        if idxs.len() == 0 {
-            break
+/// (front matter, rest of the document)
+pub fn split_front_matter(doc: &str) -> Result<(&str, &str), StcError> {
+    if doc.starts_with("---\n") { // extract front matter
+        let fm_end = doc.find("\n---\n").ok_or(StcError::BadFrontMatter)?;
+        Ok((&doc[4..fm_end], &doc[fm_end + 5..]))
+    }
+    else { // if no front matter found, empty string
+        Ok(("", doc))
+    }
+}
+
+/// find every unescaped `##NAME##` tag. returns (start, end, name), with start..end covering the whole tag
+pub fn find_tags(s: &str) -> Vec<(usize, usize, &str)> {
+    let b = s.as_bytes();
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while let Some(off) = s[i..].find("##") {
+        let start = i + off;
+        let name_start = start + 2;
+        let name_len = s[name_start..].find(['#', '\n']).unwrap_or(s.len() - name_start);
+        let name_end = name_start + name_len;
+        if name_len > 0 && s[name_end..].starts_with("##") {
+            if start == 0 || b[start - 1] != b'\\' {
+                ret.push((start, name_end + 2, &s[name_start..name_end]));
+            } // escaped tags are skipped whole, so their closing hashes can't start another tag
+            i = name_end + 2;
         }
-        else {
-            ret.replace_range(idxs[0]..idxs[0] + pat.len(), rep)
+        else { // not a tag, try again from the next char
+            i = start + 1;
         }
     }
     ret
 }
 
-pub fn replace_unused_tags(s: &str) -> String {
-    let re = Regex::new(r"(^|[^\\])##([^#\n]+)##").unwrap();
-    re.replace_all(s, "").to_string()
-    // TODO: remove escapes from escaped tags
-}
 
-pub fn split_doc(mut doc: &str) -> Result<(&str, HashMap<String, String>), StcError> {
+/// split a document into its `##NAME##` sections. tags inside template directives (`##if:...##`, `##for:...##` etc.)
+/// belong to the directive, so they don't start a new section
+pub fn split_sections(doc: &str) -> Result<HashMap<String, String>, StcError> {
     let mut ret = HashMap::new();
-
-    let front_matter = if doc.starts_with("---\n") { // extract front matter
-        let fm_end = doc.find("\n---\n").ok_or(StcError::BadFrontMatter)?;
-        let fm = &doc[4..fm_end];
-        doc = &doc[fm_end + 5..];
-        fm
-    }
-    else { // if no front matter found, empty string
-        ""
-    };
     //println!("{}", doc);
 
-    let re = Regex::new(r"(^|[^\\])##([^#\n]+)##").unwrap(); // ok this is where it gets funky
-    let mut caps = re.captures_iter(doc).peekable(); // we need peekable. you will see why later
-    loop {
-        let c = match caps.next() { // get the next section label
-            Some(v) => v?,
-            None => break
-        }; // catch errors
-
-        let name = c.get(2).unwrap().as_str(); // extract section name
-
-        let full = c.get(0).unwrap(); // get full match, for start/end positions
-        let sec_start = full.end(); // section starts where start label ends
-        let content = match caps.peek() { // get NEXT label to find sec end
-            Some(m2_r) => {
-                let m2 = m2_r.as_ref().unwrap(); // unwrap has to be used here, because borrow checker fuckery
-                let sec_end = m2.get(0).unwrap().start();
-                &doc[sec_start..sec_end]
-            }
-            None => { // if no next label, current sec goes to document end
-                &doc[sec_start..]
+    let mut depth = 0; // how many directives deep we are
+    let mut current: Option<(&str, usize)> = None; // name and start of the section we're in
+    for (start, end, tag) in find_tags(doc) {
+        if depth == 0 && !is_directive(tag) { // section label
+            if let Some((name, sec_start)) = current {
+                ret.insert(String::from(name), String::from(&doc[sec_start..start]));
             }
-        };
+            current = Some((tag, end)); // section starts where its label ends
+        }
+        depth = (depth + nesting(tag)).max(0);
+    }
+    if let Some((name, sec_start)) = current { // last section goes to document end
+        ret.insert(String::from(name), String::from(&doc[sec_start..]));
+    }
 
-        //println!("containing {}", content);
+    Ok(ret)
+}
 
-        ret.insert(String::from(name), String::from(content));
+/// front matter is single-line scf, but blank lines are allowed
+pub fn parse_front_matter(fm: &str) -> Result<HashMap<String, String>, StcError> {
+    let mut cfg = HashMap::new();
+    for c in fm.split('\n') {
+        if c.trim() != "" {
+            let (k, v) = parse_rep(c)?;
+            cfg.insert(k, v);
+        }
     }
+    Ok(cfg)
+}
 
-    Ok((front_matter, ret))
+/// write a file in the staging dir. it may be a hard link to the live output, so unlink it first instead of writing through
+pub fn write_fresh(p: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    unlink(p.as_ref())?;
+    fs::write(p, contents)
+}
+
+/// `fs::copy`, but into a fresh file, like `write_fresh`
+pub fn copy_fresh(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    unlink(to.as_ref())?;
+    fs::copy(from, to).map(|_| ())
+}
+
+fn unlink(p: &Path) -> io::Result<()> {
+    match fs::remove_file(p) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(())
+    }
 }
 
 pub fn read_or_none(p: impl AsRef<Path>) -> Result<Option<String>, StcError> {
     //println!("reading {} or none", p.as_ref().to_string_lossy());
     match read_to_string(p) {
         Ok(v) => {
-            Ok(if v != "" {
+            Ok(if !v.is_empty() {
                 Some(v)
             }
             else {
@@ -118,7 +143,7 @@ pub fn read_or_none(p: impl AsRef<Path>) -> Result<Option<String>, StcError> {
 pub fn parse_rep(s: &str) -> Result<(String, String), StcError> {
     match s.split_once('=') {
         Some((name, body)) => Ok((name.trim().into(), body.trim().into())),
-        None => return Err(StcError::CfgErr(String::from(s)))
+        None => Err(StcError::BadLine(String::from(s)))
     }
 }
 pub fn parse_shit_markup(s: &str) -> Result<Vec<(String, String)>, StcError> {
@@ -158,18 +183,97 @@ pub fn os_str_to_str_or_err(s: &OsStr) -> Result<&str, StcError> { // helper fn
     }
 }
 
+/// where a page ends up, relative to the output dir. with pretty urls, `foo.md` goes to `foo/index.html`
+pub fn output_path(page: &Path, pretty: bool) -> PathBuf {
+    let mut out = PathBuf::from(page);
+    if pretty && page.file_stem().map(|s| s != "index").unwrap_or(false) {
+        out.set_extension("");
+        out.push("index.html");
+    }
+    else {
+        out.set_extension("html");
+    }
+    out
+}
+
+/// whether a relative path stays inside the dir it's joined onto. no `..`, no absolute paths
+pub fn stays_inside(p: &Path) -> bool {
+    p.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// a page's url, relative to the site root. with pretty urls, that's the directory (`foo/`) rather than the file
+pub fn page_url(page: &Path, pretty: bool) -> Result<String, StcError> {
+    let out = output_path(page, pretty);
+    let url = os_str_to_str_or_err(out.as_os_str())?;
+    Ok(if pretty {
+        url.strip_suffix("index.html").unwrap_or(url).into()
+    }
+    else {
+        url.into()
+    })
+}
+
 pub fn is_markdown(p: impl AsRef<Path>) -> Result<bool, StcError> {
     let ext = match p.as_ref().extension() { // if no extension, it's not markdown
         Some(v) => v,
         None => return Ok(false)
     };
     let ext_uni = os_str_to_str_or_err(ext)?; // error out on non-unicode
-    Ok(if ext_uni == "md" {
-        true
+    Ok(ext_uni == "md")
+}
+
+/// mime type from the extension. used for serving and for rss enclosures
+pub fn content_type(p: &Path) -> &'static str {
+    match p.extension().and_then(|e| e.to_str()).unwrap_or("") {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream"
+    }
+}
+
+/// fnv-1a. unlike the std hasher it's the same from run to run, so hashes can be saved to disk
+pub struct Fnv(u64);
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
     }
-    else {
-        false
-    })
+}
+pub fn stable_hash(v: &impl Hash) -> u64 {
+    let mut h = Fnv::default();
+    v.hash(&mut h);
+    h.finish()
 }
 
 /*pub trait OptionHelpers<T> {
@@ -188,22 +292,119 @@ impl<T> OptionHelpers<T> for Option<T> {
     }
 }*/
 
+/// where in a source file an error came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceContext {
+    pub file: PathBuf,
+    pub line: usize, // from 1. 0 if we only know the file
+    pub col: usize, // from 1
+    pub snippet: String, // the offending line
+}
+
+impl StcError {
+    /// hint that the error is somewhere after the line `hint`, eg. a channel's id in `cfg/channels`
+    pub fn near(self, hint: &str) -> StcError {
+        StcError::Near(hint.into(), Box::new(self))
+    }
+
+    /// attach the file (and the line, if it can be found) an error came from. `src` is the file's contents
+    pub fn in_file(self, file: impl AsRef<Path>, src: &str) -> StcError {
+        let (hint, err) = match self {
+            StcError::At(..) => return self, // already knows where it's from
+            StcError::Near(h, e) => (Some(h), *e),
+            e => (None, e)
+        };
+
+        let lines: Vec<&str> = src.split('\n').collect();
+        let start = hint.as_ref() // skip to the hint, if there is one
+            .and_then(|h| lines.iter().position(|l| l.trim() == h))
+            .unwrap_or(0);
+        let found = err.snippet()
+            .and_then(|n| lines.iter().enumerate().skip(start).find_map(|(i, l)| l.find(&n).map(|c| (i, l[..c].chars().count()))))
+            .or_else(|| hint.map(|_| (start, 0)));
+
+        let ctx = match found {
+            Some((line, col)) => SourceContext {
+                file: file.as_ref().into(),
+                line: line + 1,
+                col: col + 1,
+                snippet: lines[line].into(),
+            },
+            None => SourceContext {
+                file: file.as_ref().into(),
+                line: 0, col: 0,
+                snippet: String::new(),
+            }
+        };
+        StcError::At(Box::new(ctx), Box::new(err))
+    }
+
+    /// something to look for in the source to find the line this error is about
+    fn snippet(&self) -> Option<String> {
+        match self {
+            StcError::BadLine(v) | StcError::CfgErr(v) => Some(v.trim().to_owned()).filter(|v| !v.is_empty()),
+            StcError::BadFrontMatter => Some("---".into()),
+            StcError::BadOption(k, v) => Some(format!("{}={}", k, v)),
+            StcError::RssError(RssError::BadPubdate(_)) => Some("rss_pubdate".into()),
+            StcError::RssError(RssError::ChannelNotFound(v) | RssError::BadFormat(v) | RssError::BadLimit(v) | RssError::BadTtl(v)
+                | RssError::OutsideOutput(v) | RssError::MissingEnclosure(v)) => Some(v.clone()),
+            StcError::SitemapError(SitemapError::BadChangefreq(v) | SitemapError::BadPriority(v) | SitemapError::BadLastmod(v)
+                | SitemapError::OutsideOutput(v)) => Some(v.clone()),
+            StcError::Near(_, e) => e.snippet(),
+            _ => None
+        }
+    }
+
+    /// a human readable report, with the offending line if we know it
+    pub fn diagnostic(&self) -> String {
+        let show = |p: &Path| { // relative to the site dir is easier to read
+            let cwd = std::env::current_dir().unwrap_or_default();
+            p.strip_prefix(cwd).unwrap_or(p).to_string_lossy().to_string()
+        };
+        match self {
+            StcError::At(ctx, e) if ctx.line == 0 => format!("error: {}\n  --> {}", e, show(&ctx.file)),
+            StcError::At(ctx, e) => {
+                let num = ctx.line.to_string();
+                let pad = " ".repeat(num.len());
+                format!("error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}^",
+                    e, pad, show(&ctx.file), ctx.line, ctx.col,
+                    pad, num, ctx.snippet, pad, " ".repeat(ctx.col - 1))
+            }
+            e => format!("error: {}", e)
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum StcError {
-    #[error("bad front matter formatting")]
+    #[error("bad front matter formatting: no closing ---")]
     BadFrontMatter,
-    #[error("regex error")]
-    RegexErr(#[from]fancy_regex::Error),
-    #[error("internal fs error")]
+    #[error("internal fs error: {0}")]
     FsError(#[from]io::Error),
-    #[error("malformed config")]
+    #[error("malformed config: {0}")]
     CfgErr(String),
-    #[error("non-unicode path")]
+    #[error("malformed config: expected KEY=VALUE, got \"{0}\"")]
+    BadLine(String),
+    #[error("non-unicode path: {0}")]
     PathErr(String),
-    #[error("missing template error")]
+    #[error("template error: {0}")]
     TemplateError(String),
-    #[error("blog data error")]
+    #[error("include error: {0}")]
+    IncludeError(String),
+    #[error("{0}")]
     RssError(#[from] RssError),
+    #[error("{0}")]
+    SitemapError(#[from] SitemapError),
+    #[error("bad value for {0}: {1}")]
+    BadOption(String, String),
+    #[error("already exists, not overwriting: {0}")]
+    AlreadyExists(String),
+    #[error("build failed with {0} error(s)")]
+    BuildFailed(usize),
+    #[error("{1}")]
+    Near(String, Box<StcError>),
+    #[error("{}: {1}", .0.file.to_string_lossy())]
+    At(Box<SourceContext>, Box<StcError>),
 }
 
 #[cfg(test)]
@@ -216,13 +417,6 @@ mod tests {
         let idxs = find_all_unescaped(s, "##HEAD##");
         assert_eq!(idxs, vec![12])
     }
-    #[test]
-    fn replace_all_unescaped_t() {
-        let s = "test string ##HEAD## \\##HEAD## test ##HEAD## padding";
-        let output = replace_all_unescaped(s, "##HEAD##", "aaa");
-        assert_eq!(output, String::from("test string aaa \\##HEAD## test aaa padding"))
-    }
-
     #[test]
     fn split_doc_t() {
         let s = r"---
@@ -232,10 +426,38 @@ test
 aaaa
 \##TEST##
         ";
-        let (fm, sections) = split_doc(s).unwrap();
+        let (fm, doc) = split_front_matter(s).unwrap();
+        let sections = split_sections(doc).unwrap();
         assert_eq!(fm, "test");
-        assert!(sections.get("MAIN").is_some());
-        assert!(sections.get("TEST").is_none());
+        assert!(sections.contains_key("MAIN"));
+        assert!(!sections.contains_key("TEST"));
+    }
+
+    #[test]
+    fn split_sections_directives_t() {
+        let s = "##BODY##\nposts:\n##for:rss=blog##\n##item_title##\n##endfor##\n##FOOT##\nfoot";
+        let sections = split_sections(s).unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections["BODY"], "\nposts:\n##for:rss=blog##\n##item_title##\n##endfor##\n");
+        assert_eq!(sections["FOOT"], "\nfoot");
+    }
+
+    #[test]
+    fn output_path_t() {
+        assert_eq!(output_path(Path::new("blog/post.md"), false), PathBuf::from("blog/post.html"));
+        assert_eq!(output_path(Path::new("blog/post.md"), true), PathBuf::from("blog/post/index.html"));
+        assert_eq!(output_path(Path::new("blog/index.md"), true), PathBuf::from("blog/index.html"));
+        assert_eq!(page_url(Path::new("blog/post.md"), true).unwrap(), "blog/post/");
+        assert_eq!(page_url(Path::new("blog/index.md"), true).unwrap(), "blog/");
+        assert_eq!(page_url(Path::new("index.md"), true).unwrap(), "");
+        assert_eq!(page_url(Path::new("index.md"), false).unwrap(), "index.html");
+    }
+
+    #[test]
+    fn stays_inside_t() {
+        assert!(stays_inside(Path::new("blog/./rss.xml")));
+        assert!(!stays_inside(Path::new("blog/../../rss.xml")));
+        assert!(!stays_inside(Path::new("/etc/passwd")));
     }
 
     #[test]
@@ -255,4 +477,17 @@ name3=val3";
         assert_eq!(p[1], ("name2".into(), "val2".into()));
         assert_eq!(p[2], ("name3".into(), "val3".into()));
     }
+    #[test]
+    fn in_file_t() {
+        let src = "feed1\ntitle=a\n----\nfeed2\ntitle=b\nttl=x\nlimit";
+        let e = parse_singleline_scf("title=b\nttl=x\nlimit").unwrap_err().near("feed2").in_file("channels", src);
+        match &e {
+            StcError::At(ctx, inner) => {
+                assert_eq!((ctx.line, ctx.col, ctx.snippet.as_str()), (7, 1, "limit"));
+                assert!(matches!(**inner, StcError::BadLine(_)));
+            }
+            _ => panic!("no context: {:?}", e)
+        }
+        assert!(e.diagnostic().contains("channels:7:1"));
+    }
 }